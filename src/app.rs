@@ -1,24 +1,56 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
+use chrono::Local;
+use ratatui::layout::Rect;
 use ratatui::prelude::Color;
 use ratatui::style::palette::tailwind;
-use ratatui::widgets::{ScrollbarState, TableState};
+use ratatui::widgets::{ListState, ScrollbarState, TableState};
+use serde::Deserialize;
 
 use unicode_width::UnicodeWidthStr;
 use virt::connect::Connect;
 
+use crate::cli::Args;
+use crate::collector::{Collector, GuestAddresses, Handoff, Sample, Update};
+use crate::config::Config;
+use crate::console::Console;
+use crate::event::Event;
+use crate::export::ExportFormat;
+use crate::keymap::Keymap;
+use crate::log::{Level, Log};
+use crate::network::NetworkInfo;
+use crate::storage::{PoolInfo, VolumeInfo};
+use crate::units::Units;
 use crate::vms::*;
 
 pub type AppResult<T> = std::result::Result<T, Box<dyn error::Error>>;
 
-const PALETTES: [tailwind::Palette; 4] = [
+pub(crate) const PALETTES: [tailwind::Palette; 4] = [
     tailwind::BLUE,
     tailwind::EMERALD,
     tailwind::INDIGO,
     tailwind::RED,
 ];
 
-const ITEM_HEIGHT: usize = 4;
+/// How long a status message stays on screen.
+pub const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause after which typing a VM name starts over, and the jump ends.
+pub const JUMP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Number of samples kept in the usage history of each VM.
+pub const HISTORY_LEN: usize = 60;
+
+/// Reported for actions on a tab whose hypervisor was never reached.
+pub const NOT_CONNECTED: &str = "Not connected to this hypervisor";
 
 #[derive(Debug)]
 pub struct TableColors {
@@ -30,6 +62,10 @@ pub struct TableColors {
     pub normal_row_color: Color,
     pub alt_row_color: Color,
     pub footer_border_color: Color,
+    pub running_fg: Color,
+    pub paused_fg: Color,
+    pub stopped_fg: Color,
+    pub crashed_fg: Color,
 }
 
 impl TableColors {
@@ -43,6 +79,22 @@ impl TableColors {
             normal_row_color: tailwind::SLATE.c950,
             alt_row_color: tailwind::SLATE.c900,
             footer_border_color: color.c400,
+            running_fg: tailwind::GREEN.c400,
+            paused_fg: tailwind::YELLOW.c400,
+            stopped_fg: tailwind::SLATE.c500,
+            crashed_fg: tailwind::RED.c400,
+        }
+    }
+
+    /// Foreground color of the status cell for a domain in `state`.
+    pub fn state_fg(&self, state: DomainState) -> Color {
+        match state {
+            DomainState::Running | DomainState::Blocked => self.running_fg,
+            DomainState::Paused | DomainState::PmSuspended | DomainState::Shutdown => {
+                self.paused_fg
+            }
+            DomainState::Crashed => self.crashed_fg,
+            DomainState::ShutOff | DomainState::NoState | DomainState::Unknown => self.stopped_fg,
         }
     }
 }
@@ -51,204 +103,2043 @@ impl TableColors {
 pub struct TableData {
     pub id: String,
     pub name: String,
+    /// The name, followed by the title of the VM if it has one.
+    pub label: String,
+    pub vcpus: String,
     pub cpu_usage: String,
     pub mem_usage: String,
     pub status: String,
+    pub state: DomainState,
 }
 
 impl TableData {
-    pub const fn ref_array(&self) -> [&String; 5] {
-        [
-            &self.id,
-            &self.name,
-            &self.cpu_usage,
-            &self.mem_usage,
-            &self.status,
-        ]
+    /// Text of the cell in `column`.
+    pub fn cell(&self, column: Column) -> &str {
+        match column {
+            Column::Id => &self.id,
+            Column::Name => &self.label,
+            Column::Vcpus => &self.vcpus,
+            Column::Cpu => &self.cpu_usage,
+            Column::Memory => &self.mem_usage,
+            Column::Status => &self.status,
+        }
+    }
+}
+
+/// A column of the VM table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    Id,
+    Name,
+    Vcpus,
+    Cpu,
+    Memory,
+    Status,
+}
+
+impl Column {
+    /// Every column, in the order they are shown.
+    pub const ALL: [Self; 6] = [
+        Self::Id,
+        Self::Name,
+        Self::Vcpus,
+        Self::Cpu,
+        Self::Memory,
+        Self::Status,
+    ];
+
+    /// Header of the column.
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Name => "name",
+            Self::Vcpus => "vcpus",
+            Self::Cpu => "cpu usage",
+            Self::Memory => "memory usage",
+            Self::Status => "status",
+        }
     }
 
-    fn id(&self) -> &str {
-        &self.id
+    /// Name of the column in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Name => "name",
+            Self::Vcpus => "vcpus",
+            Self::Cpu => "cpu",
+            Self::Memory => "memory",
+            Self::Status => "status",
+        }
     }
 
-    fn name(&self) -> &str {
-        &self.name
+    /// Space separated names of `columns`, as in the config file.
+    pub fn names(columns: &[Self]) -> String {
+        columns
+            .iter()
+            .map(|column| column.name())
+            .collect::<Vec<_>>()
+            .join(" ")
     }
 
-    fn cpu_usage(&self) -> &str {
-        &self.cpu_usage
+    /// Parses space separated column names. They are shown in the usual
+    /// order whatever the order they are listed in.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, String> {
+        let mut columns = vec![];
+        for name in s.split_whitespace() {
+            match Self::ALL.into_iter().find(|column| column.name() == name) {
+                Some(column) => columns.push(column),
+                None => return Err(format!("unknown column {}", name)),
+            }
+        }
+        if columns.is_empty() {
+            return Err(String::from("no columns listed"));
+        }
+        Ok(Self::ALL
+            .into_iter()
+            .filter(|column| columns.contains(column))
+            .collect())
     }
+}
+
+/// A lifecycle action on a single VM that has to be confirmed before it runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VmAction {
+    Start,
+    Shutdown,
+    ForceOff,
+    Reboot,
+    /// Send the Ctrl+Alt+Del key sequence.
+    CtrlAltDel,
+    /// Save the memory to disk and stop.
+    ManagedSave,
+    /// Discard the saved memory image.
+    ManagedSaveRemove,
+    /// Live migrate to the hypervisor at the given URI.
+    Migrate(String),
+    /// Revert to the named snapshot, discarding the current state.
+    RevertSnapshot(String),
+    /// Remove the definition of a shut off VM, and its disk images if asked.
+    Undefine {
+        remove_storage: bool,
+    },
+    /// Take a snapshot with a generated name, as part of a batch.
+    Snapshot {
+        disk_only: bool,
+    },
+}
 
-    fn mem_usage(&self) -> &str {
-        &self.mem_usage
+impl VmAction {
+    /// Question asked before running the action on `vm_name`.
+    pub fn question(&self, vm_name: &str) -> String {
+        match self {
+            Self::Start => format!("Start {}?", vm_name),
+            Self::Shutdown => format!("Shut down {}?", vm_name),
+            Self::ForceOff => format!("Force off {}?", vm_name),
+            Self::Reboot => format!("Reboot {}?", vm_name),
+            Self::CtrlAltDel => format!(
+                "Send Ctrl+Alt+Del to {}? Most guests reboot on it.",
+                vm_name
+            ),
+            Self::ManagedSave => format!(
+                "Save the memory of {} to disk and stop it? Starting it restores it.",
+                vm_name
+            ),
+            Self::ManagedSaveRemove => format!(
+                "Discard the saved memory of {}? It will cold boot on the next start.",
+                vm_name
+            ),
+            Self::Migrate(uri) => format!(
+                "Live migrate {} to {}? It moves there for good and vmgr is blocked until it's done.",
+                vm_name, uri
+            ),
+            Self::RevertSnapshot(snapshot) => format!(
+                "Revert {} to snapshot {}? Its current state will be lost.",
+                vm_name, snapshot
+            ),
+            Self::Undefine { remove_storage } => format!(
+                "Delete {} permanently? Its definition, snapshots and NVRAM will be removed. {}",
+                vm_name,
+                if *remove_storage {
+                    "ITS DISK IMAGES WILL BE DELETED TOO."
+                } else {
+                    "Disk images are kept, (s) to delete them too."
+                }
+            ),
+            Self::Snapshot { disk_only: true } => format!("Snapshot the disks of {}?", vm_name),
+            Self::Snapshot { disk_only: false } => format!(
+                "Snapshot the disks and memory of {}? Running vms are paused meanwhile.",
+                vm_name
+            ),
+        }
     }
 
-    fn status(&self) -> &str {
-        &self.status
+    /// Status message shown after the action succeeded on `vm_name`.
+    pub fn done(&self, vm_name: &str) -> String {
+        match self {
+            Self::Start => format!("Started {}", vm_name),
+            Self::Shutdown => format!("Shutting down {}", vm_name),
+            Self::ForceOff => format!("Forced off {}", vm_name),
+            Self::Reboot => format!("Rebooting {}", vm_name),
+            Self::CtrlAltDel => format!("Sent Ctrl+Alt+Del to {}", vm_name),
+            Self::ManagedSave => format!("Saved {}", vm_name),
+            Self::ManagedSaveRemove => format!("Discarded the saved memory of {}", vm_name),
+            Self::Migrate(uri) => format!("Migrated {} to {}", vm_name, uri),
+            Self::RevertSnapshot(snapshot) => {
+                format!("Reverted {} to snapshot {}", vm_name, snapshot)
+            }
+            Self::Undefine { .. } => format!("Deleted {}", vm_name),
+            Self::Snapshot { .. } => format!("Created snapshot of {}", vm_name),
+        }
+    }
+
+    /// Name of the action as shown in status messages.
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Self::Start => "Start",
+            Self::Shutdown => "Shutdown",
+            Self::ForceOff => "Force off",
+            Self::Reboot => "Reboot",
+            Self::CtrlAltDel => "Ctrl+Alt+Del",
+            Self::ManagedSave => "Save",
+            Self::ManagedSaveRemove => "Discard save",
+            Self::Migrate(_) => "Migration",
+            Self::RevertSnapshot(_) => "Revert",
+            Self::Undefine { .. } => "Delete",
+            Self::Snapshot { .. } => "Snapshot",
+        }
     }
 }
 
-/// Application.
+/// Question asked before running a batch of actions, one per VM: the
+/// question of each action, asked once for all the VMs it applies to.
+pub fn batch_question(batch: &[(VmAction, String)]) -> String {
+    let mut questions: Vec<(&VmAction, Vec<&str>)> = vec![];
+    for (action, vm_name) in batch {
+        match questions.iter_mut().find(|(a, _)| *a == action) {
+            Some((_, names)) => names.push(vm_name),
+            None => questions.push((action, vec![vm_name])),
+        }
+    }
+    questions
+        .iter()
+        .map(|(action, names)| action.question(&names.join(", ")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// What a [`Form`] collects its fields for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormKind {
+    /// Name and description of a new snapshot.
+    Snapshot { disk_only: bool },
+    /// Name, vCPUs, memory and disk of a VM to define.
+    NewVm,
+    /// Name of a copy of the VM.
+    Clone,
+    /// Hypervisor to migrate the VM to.
+    Migrate,
+    /// New balloon target of a running VM.
+    Memory,
+    /// New number of active vCPUs of a running VM.
+    Vcpus,
+    /// New disk and network bandwidth caps of a running VM.
+    IoLimits,
+    /// vCPU of a running VM to pin, and the host CPUs to pin it to.
+    PinVcpu,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct FormField {
+    pub label: &'static str,
+    pub value: String,
+    /// Shown greyed out while the field is empty.
+    pub placeholder: String,
+}
+
+impl FormField {
+    pub fn new(label: &'static str, placeholder: &str) -> Self {
+        Self {
+            label,
+            value: String::new(),
+            placeholder: placeholder.to_string(),
+        }
+    }
+}
+
+/// A dialog collecting one or more text fields for an action on `vm_name`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Form {
+    pub kind: FormKind,
+    pub vm_name: String,
+    pub fields: Vec<FormField>,
+    /// Index of the field receiving key presses.
+    pub focus: usize,
+    /// Extra information shown under the fields.
+    pub note: Option<&'static str>,
+    /// Why the last submit was rejected.
+    pub error: Option<String>,
+}
+
+impl Form {
+    pub fn new(kind: FormKind, vm_name: String, fields: Vec<FormField>) -> Self {
+        Self {
+            kind,
+            vm_name,
+            fields,
+            focus: 0,
+            note: None,
+            error: None,
+        }
+    }
+
+    pub fn with_note(mut self, note: &'static str) -> Self {
+        self.note = Some(note);
+        self
+    }
+
+    pub fn title(&self) -> String {
+        match self.kind {
+            FormKind::Snapshot { disk_only: true } => {
+                format!("Snapshot {} (disks only)", self.vm_name)
+            }
+            FormKind::Snapshot { disk_only: false } => {
+                format!("Snapshot {} (disks and memory)", self.vm_name)
+            }
+            FormKind::NewVm => String::from("New vm"),
+            FormKind::Clone => format!("Clone {}", self.vm_name),
+            FormKind::Migrate => format!("Migrate {}", self.vm_name),
+            FormKind::Memory => format!("Set memory of {}", self.vm_name),
+            FormKind::Vcpus => format!("Set vCPUs of {}", self.vm_name),
+            FormKind::IoLimits => format!("Limit the bandwidth of {}", self.vm_name),
+            FormKind::PinVcpu => format!("Pin a vCPU of {}", self.vm_name),
+        }
+    }
+
+    /// Trimmed value of the `i`th field.
+    pub fn value(&self, i: usize) -> &str {
+        self.fields[i].value.trim()
+    }
+}
+
+/// Pane navigation keys act on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Focus {
+    #[default]
+    Table,
+    Overview,
+}
+
+/// Scroll position of a pane whose content can outgrow it.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PaneScroll {
+    /// Index of the first visible line.
+    pub offset: usize,
+    /// Number of lines of content, and of those that fit, the last time the
+    /// pane was rendered.
+    pub lines: usize,
+    pub page_size: usize,
+    pub state: ScrollbarState,
+}
+
+impl PaneScroll {
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll_to(self.offset.saturating_add(n));
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll_to(self.offset.saturating_sub(n));
+    }
+
+    pub fn scroll_to(&mut self, offset: usize) {
+        self.offset = offset.min(self.lines.saturating_sub(self.page_size));
+        self.state = self.state.position(self.offset);
+    }
+
+    /// Takes in how much content there is and how much of it fits, keeping
+    /// the position within it.
+    pub fn resize(&mut self, lines: usize, page_size: usize) {
+        self.lines = lines;
+        self.page_size = page_size.max(1);
+        self.state = self
+            .state
+            .content_length(lines.saturating_sub(self.page_size));
+        self.scroll_to(self.offset);
+    }
+}
+
+/// Read-only text shown full screen with its own scroll position.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TextView {
+    pub title: String,
+    pub lines: Vec<String>,
+    /// Index of the first visible line.
+    pub offset: usize,
+    /// Number of lines that fit on screen the last time the view was rendered.
+    pub page_size: usize,
+    pub scroll_state: ScrollbarState,
+}
+
+impl TextView {
+    pub fn new(title: String, text: &str) -> Self {
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        Self {
+            title,
+            scroll_state: ScrollbarState::new(lines.len()),
+            lines,
+            offset: 0,
+            page_size: 1,
+        }
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll_to(self.offset.saturating_add(n));
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll_to(self.offset.saturating_sub(n));
+    }
+
+    pub fn scroll_to(&mut self, offset: usize) {
+        self.offset = offset.min(self.lines.len().saturating_sub(self.page_size));
+        self.scroll_state = self.scroll_state.position(self.offset);
+    }
+}
+
+/// The storage pools of the current host, or the volumes of one of them.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StorageView {
+    pub pools: Vec<PoolInfo>,
+    pub pool_state: TableState,
+    /// Name and volumes of the pool drilled into, if any.
+    pub volumes: Option<(String, Vec<VolumeInfo>)>,
+    pub volume_state: TableState,
+}
+
+impl StorageView {
+    pub fn new(pools: Vec<PoolInfo>) -> Self {
+        let selected = (!pools.is_empty()).then_some(0);
+        Self {
+            pools,
+            pool_state: TableState::default().with_selected(selected),
+            ..Self::default()
+        }
+    }
+
+    /// The highlighted pool.
+    pub fn selected_pool(&self) -> Option<&PoolInfo> {
+        self.pool_state.selected().and_then(|i| self.pools.get(i))
+    }
+
+    /// Shows the volumes of `pool` instead of the pools.
+    pub fn open_pool(&mut self, pool: String, volumes: Vec<VolumeInfo>) {
+        let selected = (!volumes.is_empty()).then_some(0);
+        self.volume_state = TableState::default().with_selected(selected);
+        self.volumes = Some((pool, volumes));
+    }
+
+    /// Moves the highlight of the shown list by `delta` rows.
+    pub fn scroll(&mut self, delta: isize) {
+        match &self.volumes {
+            Some((_, volumes)) => scroll_table(&mut self.volume_state, volumes.len(), delta),
+            None => scroll_table(&mut self.pool_state, self.pools.len(), delta),
+        }
+    }
+}
+
+/// The virtual networks of the current host.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct NetworkView {
+    pub networks: Vec<NetworkInfo>,
+    pub table_state: TableState,
+}
+
+impl NetworkView {
+    pub fn new(networks: Vec<NetworkInfo>) -> Self {
+        let selected = (!networks.is_empty()).then_some(0);
+        Self {
+            networks,
+            table_state: TableState::default().with_selected(selected),
+        }
+    }
+
+    /// Moves the highlight by `delta` rows.
+    pub fn scroll(&mut self, delta: isize) {
+        scroll_table(&mut self.table_state, self.networks.len(), delta);
+    }
+}
+
+/// Moves the highlight of a table with `len` rows by `delta` rows, stopping
+/// at either end.
+fn scroll_table(state: &mut TableState, len: usize, delta: isize) {
+    if len == 0 {
+        return;
+    }
+    let i = state.selected().unwrap_or(0).saturating_add_signed(delta);
+    state.select(Some(i.min(len - 1)));
+}
+
+/// Domain XML waiting to be edited in the user's editor. The main loop runs
+/// the editor, as it owns the terminal, and hands the result to
+/// [`App::finish_edit`].
 #[derive(Debug)]
-pub struct App {
-    /// Is the application running?
-    pub running: bool,
-    pub conn: Connect,
+pub struct XmlEdit {
+    pub vm_name: String,
+    pub xml: String,
+}
+
+/// Input mode of the application, deciding where key events are routed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum AppMode {
+    #[default]
+    Normal,
+    /// A modal dialog is waiting for `y`/`n` before running `action` on `vm_name`.
+    Confirm { action: VmAction, vm_name: String },
+    /// A modal dialog is waiting for `y`/`n` before running each action on
+    /// its VM.
+    ConfirmBatch(Vec<(VmAction, String)>),
+    /// A modal dialog is waiting for `y`/`n` before quitting.
+    ConfirmQuit,
+    /// Key presses edit the name filter.
+    Filter,
+    /// Key presses select the first VM whose name starts with what was typed.
+    Jump,
+    /// The column picker is open, highlighting an entry of [`Column::ALL`].
+    Columns(ListState),
+    /// A full screen text view (e.g. domain XML) is open.
+    Text(TextView),
+    /// A form dialog is collecting input.
+    Form(Form),
+    /// The serial console in [`App::console`] is shown full screen.
+    Console,
+    /// The storage pools and volumes of the current host are shown full
+    /// screen.
+    Storage(StorageView),
+    /// The virtual networks of the current host are shown full screen.
+    Networks(NetworkView),
+    /// The snapshot browser for `vm_name` is open.
+    Snapshots {
+        vm_name: String,
+        snapshots: Vec<SnapshotInfo>,
+        list_state: ListState,
+    },
+}
+
+/// Column the table can be sorted by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortColumn {
+    Name,
+    Cpu,
+    Memory,
+    Status,
+}
+
+impl SortColumn {
+    /// Cycles unsorted -> name -> cpu -> memory -> status -> unsorted.
+    fn cycle(column: Option<Self>) -> Option<Self> {
+        match column {
+            None => Some(Self::Name),
+            Some(Self::Name) => Some(Self::Cpu),
+            Some(Self::Cpu) => Some(Self::Memory),
+            Some(Self::Memory) => Some(Self::Status),
+            Some(Self::Status) => None,
+        }
+    }
+
+    /// Header of the table column this sorts by.
+    pub fn title(&self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Cpu => "cpu usage",
+            Self::Memory => "memory usage",
+            Self::Status => "status",
+        }
+    }
+}
+
+/// How much room each row of the table takes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Density {
+    /// Rows padded with a blank line above and below.
+    #[default]
+    Comfortable,
+    /// Rows of a single line, fitting four times as many VMs.
+    Compact,
+}
+
+impl Density {
+    pub fn toggle(self) -> Self {
+        match self {
+            Self::Comfortable => Self::Compact,
+            Self::Compact => Self::Comfortable,
+        }
+    }
+
+    /// Name of the setting in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Comfortable => "comfortable",
+            Self::Compact => "compact",
+        }
+    }
+
+    /// Height of a table row in lines.
+    pub fn row_height(self) -> usize {
+        match self {
+            Self::Comfortable => 4,
+            Self::Compact => 1,
+        }
+    }
+
+    /// Text of a cell holding `text`.
+    pub fn pad(self, text: &str) -> String {
+        match self {
+            Self::Comfortable => format!("\n{}\n", text),
+            Self::Compact => text.to_string(),
+        }
+    }
+}
+
+/// Domain states the table can be narrowed down to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StateFilter {
+    #[default]
+    All,
+    Running,
+    Stopped,
+    Paused,
+}
+
+impl StateFilter {
+    /// Groups the table is split into when grouped by state, in order. Each
+    /// VM is in exactly one of them.
+    pub const GROUPS: [StateFilter; 3] = [Self::Running, Self::Paused, Self::Stopped];
+
+    /// Cycles all -> running -> stopped -> paused -> all.
+    fn cycle(self) -> Self {
+        match self {
+            Self::All => Self::Running,
+            Self::Running => Self::Stopped,
+            Self::Stopped => Self::Paused,
+            Self::Paused => Self::All,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Running => "running",
+            Self::Stopped => "stopped",
+            Self::Paused => "paused",
+        }
+    }
+
+    /// Is a VM in `state` shown?
+    fn matches(&self, state: DomainState) -> bool {
+        let paused = matches!(state, DomainState::Paused | DomainState::PmSuspended);
+        match self {
+            Self::All => true,
+            Self::Running => state.is_active() && !paused,
+            Self::Stopped => !state.is_active(),
+            Self::Paused => paused,
+        }
+    }
+}
+
+/// A line of the VM table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableRow {
+    /// Heading of the `count` VMs in `group`, which are left out of the
+    /// table while it is `collapsed`.
+    Group {
+        group: StateFilter,
+        count: usize,
+        collapsed: bool,
+    },
+    /// Index into `table_data`/`metrics` of a VM.
+    Vm(usize),
+}
+
+/// A hypervisor connection and the VMs on it, shown as a tab.
+#[derive(Debug)]
+pub struct Session {
+    /// URI the connection was opened with, and is reopened with.
+    pub uri: String,
+    /// Connection actions go through, `None` until the hypervisor could be
+    /// reached.
+    pub conn: Option<Connect>,
+    pub host: Option<HostInfo>,
     pub table_state: TableState,
-    pub max_item_lens: (u16, u16, u16, u16, u16),
+    /// Width of the widest cell of each column, by position in
+    /// [`Column::ALL`].
+    pub max_item_lens: [u16; Column::ALL.len()],
     pub scroll_state: ScrollbarState,
-    pub colors: TableColors,
     pub metrics: Vec<VmMetrics>,
     pub table_data: Vec<TableData>,
+    /// Rows currently shown, in display order. `table_state` selects a
+    /// position in this list.
+    pub rows: Vec<TableRow>,
+    /// UUID of the selected VM, so the selection stays on it when the rows
+    /// are rebuilt or reordered.
+    pub selected_uuid: Option<String>,
+    /// Guest agent addresses of the VM selected when the last sample was
+    /// requested.
+    pub guest_addresses: Option<GuestAddresses>,
+    /// Bandwidth caps of the VM selected when the last sample was requested,
+    /// by name.
+    pub io_limits: Option<(String, IoLimits)>,
+    /// Host CPUs each vCPU of the VM selected when the last sample was
+    /// requested may run on, by name.
+    pub vcpu_pins: Option<(String, Vec<Vec<u32>>)>,
+    /// Host CPU times of the last sample, to derive the usage from.
+    host_cpu: Option<HostCpuTimes>,
+    /// Share of the host CPU time that was busy between the last two samples,
+    /// as a percentage.
+    pub host_cpu_usage: Option<f64>,
+    /// Memory usage of the host as of the last sample.
+    pub host_memory: Option<HostMemory>,
+    /// Gathers stats in the background.
+    pub collector: Collector,
+    /// Why the hypervisor can't be reached, while the collector is trying to
+    /// (re)open the connection.
+    pub disconnected: Option<String>,
+    /// Why the last sample failed over a live connection, so a failure that
+    /// repeats on every sample is logged once.
+    sample_error: Option<String>,
+    /// Last [`HISTORY_LEN`] CPU usage percentages of each VM, oldest first,
+    /// by UUID so they follow the VM through sorting, filtering and renames.
+    pub cpu_history: HashMap<String, VecDeque<u64>>,
+    /// Last [`HISTORY_LEN`] memory usages (rss + cache, in KiB) of each VM,
+    /// oldest first, by UUID.
+    pub mem_history: HashMap<String, VecDeque<u64>>,
+    /// VMs asked to shut down, by name, with when they are forced off if
+    /// they are still running.
+    pub shutdowns: Vec<(String, Instant)>,
 }
 
-impl Default for App {
-    fn default() -> Self {
-        let conn: Connect = connect("qemu:///system");
-        let mut table_data: Vec<TableData> = vec![];
-        let metrics: Vec<VmMetrics> = get_vm_data(&conn);
+impl Session {
+    /// Starts connecting to the hypervisor at `uri`, read-only if asked to.
+    /// The connection is made by the collector in the background, so a slow
+    /// hypervisor doesn't hold up the interface; the session stays empty
+    /// until its first sample arrives.
+    fn open(uri: &str, read_only: bool) -> Self {
+        Self::with_collector(uri, Collector::spawn(uri, read_only))
+    }
+
+    /// A session for `uri` that gets its samples from `collector`.
+    fn with_collector(uri: &str, collector: Collector) -> Self {
+        Self {
+            uri: uri.to_string(),
+            conn: None,
+            host: None,
+            table_state: TableState::default(),
+            max_item_lens: [0; Column::ALL.len()],
+            scroll_state: ScrollbarState::default(),
+            metrics: vec![],
+            table_data: vec![],
+            rows: vec![],
+            selected_uuid: None,
+            guest_addresses: None,
+            io_limits: None,
+            vcpu_pins: None,
+            host_cpu: None,
+            host_cpu_usage: None,
+            host_memory: None,
+            collector,
+            disconnected: None,
+            sample_error: None,
+            cpu_history: HashMap::new(),
+            mem_history: HashMap::new(),
+            shutdowns: vec![],
+        }
+    }
+
+    /// Is the hypervisor being connected to for the first time?
+    pub fn is_connecting(&self) -> bool {
+        self.conn.is_none() && self.disconnected.is_none()
+    }
+
+    /// Switches to the connection for actions the collector opened, and the
+    /// host it reaches.
+    fn connect(&mut self, handoff: Handoff) {
+        if let Some(mut lost) = self.conn.replace(handoff.conn) {
+            let _ = lost.close();
+        }
+        self.host = Some(handoff.host);
+    }
+
+    fn apply_sample(&mut self, sample: Sample, units: Units, per_vcpu: bool) {
+        let mut metrics = sample.metrics;
+        // VMs without a previous sample get their rates from the baseline,
+        // the rest from the previous sample, which also carries over their
+        // uptime.
+        if let Some(baseline) = &sample.baseline {
+            derive_rates(&mut metrics, baseline);
+        }
+        derive_rates(&mut metrics, &self.metrics);
 
+        // Forget the history of VMs that went away.
+        let present = |uuid: &String| metrics.iter().any(|m| &m.uuid == uuid);
+        self.cpu_history.retain(|uuid, _| present(uuid));
+        self.mem_history.retain(|uuid, _| present(uuid));
         for domain in &metrics {
-            table_data.push(TableData {
-                id: domain.id.to_string(),
+            let history = self.cpu_history.entry(domain.uuid.clone()).or_default();
+            push_sample(history, domain.cpu_usage.round() as u64);
+            let history = self.mem_history.entry(domain.uuid.clone()).or_default();
+            push_sample(history, domain.mem_usage());
+        }
+
+        self.metrics = metrics;
+        self.format_table(units, per_vcpu);
+        if sample.guest_addresses.is_some() {
+            self.guest_addresses = sample.guest_addresses;
+        }
+        if sample.io_limits.is_some() {
+            self.io_limits = sample.io_limits;
+        }
+        if sample.vcpu_pins.is_some() {
+            self.vcpu_pins = sample.vcpu_pins;
+        }
+        self.host_cpu_usage = sample
+            .host_cpu
+            .zip(self.host_cpu)
+            .and_then(|(current, previous)| current.usage_since(&previous));
+        self.host_cpu = sample.host_cpu;
+        self.host_memory = sample.host_memory;
+    }
+
+    /// Rebuilds the table cells from the last sample, with byte counts in
+    /// `units` and CPU usage divided by the vCPU count if `per_vcpu`.
+    fn format_table(&mut self, units: Units, per_vcpu: bool) {
+        let table_data: Vec<TableData> = self
+            .metrics
+            .iter()
+            .map(|domain| TableData {
+                // Shut off domains have no id; libvirt reports -1.
+                id: if domain.id == u32::MAX {
+                    String::from("-")
+                } else {
+                    domain.id.to_string()
+                },
                 name: domain.name.clone(),
-                cpu_usage: 0.to_string(),
-                mem_usage: format!("{}", domain.mem_rss + domain.mem_cache),
-                status: if domain.status == true {
-                    String::from("on")
+                label: match domain.metadata.title.as_str() {
+                    "" => domain.name.clone(),
+                    title => format!("{} ({})", domain.name, title),
+                },
+                vcpus: domain.vcpus.to_string(),
+                cpu_usage: if per_vcpu {
+                    format!("{:.2}%", domain.cpu_usage / domain.vcpus.max(1) as f64)
                 } else {
-                    String::from("off")
+                    format!("{:.2}%", domain.cpu_usage)
                 },
+                mem_usage: mem_text(domain, units),
+                status: status_text(domain),
+                state: domain.status,
+            })
+            .collect();
+
+        self.max_item_lens = constraint_len_calculator(&table_data);
+        self.table_data = table_data;
+    }
+
+    /// Recomputes which rows are shown for `filter`, `states` and the sort
+    /// order, grouped by state when `collapsed` lists the groups to leave
+    /// out, and keeps the selection and scrollbar inside the (possibly
+    /// shrunk or grown) list.
+    fn refresh_rows(
+        &mut self,
+        filter: &str,
+        states: StateFilter,
+        sort_column: Option<SortColumn>,
+        descending: bool,
+        collapsed: Option<&[StateFilter]>,
+    ) {
+        let filter = filter.to_lowercase();
+        let mut vms: Vec<usize> = self
+            .table_data
+            .iter()
+            .enumerate()
+            .filter(|(_, data)| states.matches(data.state))
+            .filter(|(_, data)| data.label.to_lowercase().contains(&filter))
+            .map(|(i, _)| i)
+            .collect();
+
+        if let Some(column) = sort_column {
+            // Sort on the raw metrics rather than the formatted strings so
+            // numeric columns don't order lexically ("10%" < "9%").
+            let metrics = &self.metrics;
+            vms.sort_by(|&a, &b| {
+                let (a, b) = (&metrics[a], &metrics[b]);
+                let ordering = match column {
+                    SortColumn::Name => a.name.cmp(&b.name),
+                    SortColumn::Cpu => a.cpu_usage.total_cmp(&b.cpu_usage),
+                    SortColumn::Memory => a.mem_usage().cmp(&b.mem_usage()),
+                    SortColumn::Status => a.status.cmp(&b.status),
+                };
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
             });
         }
 
-        Self {
-            running: true,
-            conn,
-            table_state: TableState::default().with_selected(0),
-            max_item_lens: constraint_len_calculator(&table_data),
-            scroll_state: ScrollbarState::new((table_data.len() - 1) * ITEM_HEIGHT),
-            colors: TableColors::new(&PALETTES[0]),
-            metrics,
-            table_data,
+        self.rows = match collapsed {
+            None => vms.into_iter().map(TableRow::Vm).collect(),
+            Some(collapsed) => {
+                let mut rows = vec![];
+                for group in StateFilter::GROUPS {
+                    let members: Vec<usize> = vms
+                        .iter()
+                        .copied()
+                        .filter(|&i| group.matches(self.table_data[i].state))
+                        .collect();
+                    if members.is_empty() {
+                        continue;
+                    }
+                    let collapsed = collapsed.contains(&group);
+                    rows.push(TableRow::Group {
+                        group,
+                        count: members.len(),
+                        collapsed,
+                    });
+                    if !collapsed {
+                        rows.extend(members.into_iter().map(TableRow::Vm));
+                    }
+                }
+                rows
+            }
+        };
+
+        // Find the selected VM wherever its row ended up, or the heading of
+        // its group if that is collapsed. If it went away or is filtered
+        // out, the nearest row is selected instead.
+        let row = self.selected_uuid.as_ref().and_then(|uuid| {
+            let vm = self.metrics.iter().position(|m| &m.uuid == uuid)?;
+            let state = self.metrics[vm].status;
+            self.rows
+                .iter()
+                .position(|&row| row == TableRow::Vm(vm))
+                .or_else(|| {
+                    self.rows.iter().position(|row| {
+                        matches!(row, TableRow::Group { group, collapsed: true, .. } if group.matches(state))
+                    })
+                })
+        });
+        let selected = match (row, self.table_state.selected()) {
+            _ if self.rows.is_empty() => None,
+            (Some(i), _) => Some(i),
+            (None, Some(i)) => Some(i.min(self.rows.len() - 1)),
+            (None, None) => Some(0),
+        };
+        self.table_state.select(selected);
+        if let Some(i) = selected.and_then(|i| self.vm_at(i)) {
+            self.selected_uuid = Some(self.metrics[i].uuid.clone());
+        }
+        // The scrollbar counts rows: positions run from 0 to one less than
+        // the content length, and no rows hide it.
+        self.scroll_state = self
+            .scroll_state
+            .content_length(self.rows.len())
+            .position(selected.unwrap_or(0));
+    }
+
+    /// Returns the index into `table_data`/`metrics` of the selected row,
+    /// `None` when it is a group heading.
+    pub fn selected_index(&self) -> Option<usize> {
+        self.table_state.selected().and_then(|i| self.vm_at(i))
+    }
+
+    /// Returns the index into `table_data`/`metrics` of the VM in row `i`.
+    fn vm_at(&self, i: usize) -> Option<usize> {
+        match self.rows.get(i) {
+            Some(&TableRow::Vm(index)) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Returns the group of the selected row when grouped by state: the
+    /// group of the selected VM, or the one the selected heading is of.
+    fn selected_group(&self) -> Option<StateFilter> {
+        match self.rows.get(self.table_state.selected()?)? {
+            TableRow::Group { group, .. } => Some(*group),
+            TableRow::Vm(_) => {
+                let state = self.metrics[self.selected_index()?].status;
+                StateFilter::GROUPS.into_iter().find(|g| g.matches(state))
+            }
         }
     }
+
+    /// Sums the vCPUs and memory (in KiB) assigned to all running VMs.
+    pub fn allocated(&self) -> (u64, u64) {
+        self.metrics
+            .iter()
+            .filter(|m| m.status.is_active())
+            .fold((0, 0), |(vcpus, mem), m| {
+                (vcpus + m.vcpus, mem + m.mem_current)
+            })
+    }
+
+    fn select(&mut self, i: usize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        self.table_state.select(Some(i));
+        // A group heading keeps the last VM, which is found again if the
+        // group is expanded.
+        if let Some(index) = self.vm_at(i) {
+            self.selected_uuid = Some(self.metrics[index].uuid.clone());
+        }
+        self.scroll_state = self.scroll_state.position(i);
+    }
+}
+
+/// Application.
+#[derive(Debug)]
+pub struct App {
+    /// Is the application running?
+    pub running: bool,
+    /// Are stats refreshed on every tick?
+    pub auto_refresh: bool,
+    pub mode: AppMode,
+    /// Settings in effect, from the config file and command line.
+    pub config: Config,
+    pub keymap: Keymap,
+    /// File CSV exports are written to, instead of a timestamped one.
+    pub csv_path: Option<PathBuf>,
+    /// File JSON exports are written to, instead of a timestamped one.
+    pub json_path: Option<PathBuf>,
+    /// One per hypervisor connected to, in the order given.
+    pub sessions: Vec<Session>,
+    /// Index into `sessions` of the tab shown.
+    pub current: usize,
+    /// Are the connections read-only? Actions that change vms are refused up
+    /// front then.
+    pub read_only: bool,
+    /// Is this a dry run? Actions that change vms are gone through up to the
+    /// libvirt call, which is reported instead of made.
+    pub dry_run: bool,
+    pub colors: TableColors,
+    /// How byte counts are shown.
+    pub units: Units,
+    /// How much room each row of the table takes.
+    pub density: Density,
+    /// Is CPU usage shown per vCPU, so a fully busy VM reads 100% whatever
+    /// its vCPU count, rather than summed over them?
+    pub per_vcpu: bool,
+    /// Are shut off VMs listed? Leaving them out keeps them from being
+    /// sampled at all.
+    pub show_inactive: bool,
+    /// Index into `PALETTES` of the active color palette.
+    pub color_index: usize,
+    /// Case-insensitive substring a VM name has to contain to be shown.
+    pub filter: String,
+    /// States a VM has to be in to be shown.
+    pub state_filter: StateFilter,
+    /// Are the rows grouped under a heading per state?
+    pub grouped: bool,
+    /// Groups whose VMs are hidden under their heading.
+    pub collapsed: Vec<StateFilter>,
+    /// Number of rows that fit in the table the last time it was rendered.
+    pub page_size: usize,
+    /// Where the table was last rendered, to map mouse clicks to rows.
+    pub table_area: Rect,
+    /// Pane navigation keys act on.
+    pub focus: Focus,
+    /// Scroll position of the overview of the selected VM.
+    pub overview_scroll: PaneScroll,
+    /// Columns shown in the table.
+    pub columns: Vec<Column>,
+    /// Column the rows are ordered by, or libvirt's order when `None`.
+    pub sort_column: Option<SortColumn>,
+    pub sort_descending: bool,
+    /// Message about the outcome of the last action, and when it was set.
+    pub status_message: Option<(String, Instant)>,
+    /// Is the status message reporting a failure?
+    pub status_is_error: bool,
+    /// Serial console attached to, kept while it is shown.
+    pub console: Option<Console>,
+    /// XML to edit once the current event is handled.
+    pub pending_edit: Option<XmlEdit>,
+    /// Start of a VM name typed to jump to it, and when it was last typed
+    /// into.
+    pub jump_prefix: String,
+    pub jump_typed_at: Instant,
+    /// UUIDs of the VMs on the current tab marked for a batch action.
+    pub marked: HashSet<String>,
+    /// Connection events, status messages and errors, for the log view.
+    pub log: Log,
+    /// Wakes the main loop from other threads, once it runs.
+    pub events: Option<Sender<Event>>,
 }
 
 impl App {
-    /// Constructs a new instance of [`App`].
-    pub fn new() -> Self {
-        return Self::default();
+    /// Constructs a new instance of [`App`] from the config file, with the
+    /// command line `args` taking precedence, and starts connecting to the
+    /// hypervisors.
+    pub fn new(args: &Args) -> AppResult<Self> {
+        let (config, mut warning) = Config::load_with_args(args);
+
+        let uris = if args.connect.is_empty() {
+            vec![config.uri.clone()]
+        } else {
+            args.connect.clone()
+        };
+        let sessions: Vec<Session> = uris
+            .iter()
+            .map(|uri| Session::open(uri, args.read_only))
+            .collect();
+
+        let keymap = match Keymap::new(&config.keys) {
+            Ok(keymap) => keymap,
+            Err(e) => {
+                warning = Some(format!("Invalid key bindings, using the defaults: {}", e));
+                Keymap::default()
+            }
+        };
+
+        let columns = match Column::parse_list(&config.columns) {
+            Ok(columns) => columns,
+            Err(e) => {
+                warning = Some(format!("Invalid columns, showing all of them: {}", e));
+                Column::ALL.to_vec()
+            }
+        };
+
+        let palette = config.palette;
+        let units = config.units;
+        let density = config.density;
+        let show_inactive = config.show_inactive;
+        let mut app = Self {
+            running: true,
+            auto_refresh: true,
+            mode: AppMode::Normal,
+            config,
+            keymap,
+            csv_path: args.csv.clone(),
+            json_path: args.json.clone(),
+            sessions,
+            current: 0,
+            read_only: args.read_only,
+            dry_run: args.dry_run,
+            colors: TableColors::new(&PALETTES[palette]),
+            units,
+            density,
+            per_vcpu: false,
+            show_inactive,
+            color_index: palette,
+            filter: String::new(),
+            state_filter: StateFilter::default(),
+            grouped: false,
+            collapsed: vec![],
+            page_size: 1,
+            table_area: Rect::default(),
+            focus: Focus::default(),
+            overview_scroll: PaneScroll::default(),
+            columns,
+            sort_column: None,
+            sort_descending: false,
+            status_message: None,
+            status_is_error: false,
+            console: None,
+            pending_edit: None,
+            jump_prefix: String::new(),
+            jump_typed_at: Instant::now(),
+            marked: HashSet::new(),
+            log: Log::open(),
+            events: None,
+        };
+        app.refresh_rows();
+        if let Some(warning) = warning {
+            app.set_error(warning);
+        }
+
+        Ok(app)
+    }
+
+    /// The session of the tab shown.
+    pub fn session(&self) -> &Session {
+        &self.sessions[self.current]
+    }
+
+    pub fn session_mut(&mut self) -> &mut Session {
+        &mut self.sessions[self.current]
+    }
+
+    /// Connection of the tab shown, `None` if its hypervisor was never
+    /// reached.
+    pub fn conn(&self) -> Option<&Connect> {
+        self.session().conn.as_ref()
+    }
+
+    /// Switches to the next tab, wrapping around.
+    pub fn next_tab(&mut self) {
+        self.current = (self.current + 1) % self.sessions.len();
+        self.marked.clear();
+    }
+
+    /// Switches to the previous tab, wrapping around.
+    pub fn prev_tab(&mut self) {
+        self.current = (self.current + self.sessions.len() - 1) % self.sessions.len();
+        self.marked.clear();
     }
 
-    /// Handles the tick event of the terminal.
+    /// Handles the tick event of the terminal: shows the newest samples from
+    /// the collectors, and asks for the next ones unless refresh is frozen.
     pub fn tick(&mut self) {
-        let mut table_data: Vec<TableData> = vec![];
-        let metrics: Vec<VmMetrics> = get_vm_data(&self.conn);
-
-        for (i, domain) in metrics.iter().enumerate() {
-            let elapsed = domain
-                .timestamp
-                .duration_since(self.metrics[i].timestamp)
-                .as_secs_f64();
-            table_data.push(TableData {
-                id: domain.id.to_string(),
-                name: domain.name.clone(),
-                cpu_usage: if elapsed > 0.0 {
-                    let time_diff = domain.cpu_time.saturating_sub(self.metrics[i].cpu_time) as f64
-                        / 1_000_000_000.0;
-                    format!("{:.2}%", (time_diff as f64 / elapsed) * 100.0)
-                } else {
-                    format!("{:.2}%", 0.0)
-                },
-                mem_usage: format!("{} Mb", (domain.mem_rss + domain.mem_cache) / 1024),
-                status: if domain.status == true {
-                    String::from("on")
+        if self.mode == AppMode::Jump && self.jump_typed_at.elapsed() >= JUMP_TIMEOUT {
+            self.mode = AppMode::Normal;
+        }
+        self.apply_samples();
+        self.escalate_shutdowns();
+        if self.auto_refresh {
+            self.refresh();
+        }
+    }
+
+    /// Forces off the VM named `name` on the current host if it is still
+    /// running [`Config::shutdown_timeout`] seconds from now, when the config
+    /// asks for that.
+    pub fn watch_shutdown(&mut self, name: &str) {
+        if !self.config.force_off_after_shutdown {
+            return;
+        }
+        let deadline = Instant::now() + Duration::from_secs(self.config.shutdown_timeout);
+        let session = self.session_mut();
+        session.shutdowns.retain(|(watched, _)| watched != name);
+        session.shutdowns.push((name.to_string(), deadline));
+    }
+
+    /// Has the collectors force off the VMs still running past their
+    /// shutdown deadline, and forgets the ones that shut off.
+    fn escalate_shutdowns(&mut self) {
+        for session in &mut self.sessions {
+            let metrics = &session.metrics;
+            session.shutdowns.retain(|(name, _)| {
+                metrics
+                    .iter()
+                    .any(|m| &m.name == name && m.status.is_active())
+            });
+
+            let now = Instant::now();
+            let (due, waiting): (Vec<_>, Vec<_>) = mem::take(&mut session.shutdowns)
+                .into_iter()
+                .partition(|(_, deadline)| *deadline <= now);
+            session.shutdowns = waiting;
+            for (name, _) in due {
+                session.collector.force_off(name);
+            }
+        }
+    }
+
+    /// Shows the newest samples from the collectors, if any arrived.
+    pub fn apply_samples(&mut self) {
+        for i in 0..self.sessions.len() {
+            let updates = match self.sessions[i].collector.updates() {
+                Ok(updates) => updates,
+                Err(_) => {
+                    let message = format!("Stats collection for {} stopped", self.sessions[i].uri);
+                    self.set_error(message);
+                    continue;
+                }
+            };
+            for update in updates {
+                let session = &mut self.sessions[i];
+                match update {
+                    Update::Connected(handoff) => {
+                        session.connect(*handoff);
+                        let reconnected = session.disconnected.take().is_some();
+                        let message = format!("Connected to {}", session.uri);
+                        if reconnected {
+                            self.report(message);
+                        } else {
+                            self.log.record(Level::Info, &message);
+                        }
+                    }
+                    Update::Sample(sample) => {
+                        session.sample_error = None;
+                        session.apply_sample(*sample, self.units, self.per_vcpu);
+                    }
+                    Update::Failed(e) => {
+                        if session.sample_error.as_ref() != Some(&e) {
+                            let message = format!("Could not sample {}: {}", session.uri, e);
+                            self.log.record(Level::Error, &message);
+                        }
+                        session.sample_error = Some(e);
+                    }
+                    Update::Disconnected(e) => {
+                        let message = format!("Connection to {} lost: {}", session.uri, e);
+                        session.disconnected = Some(e);
+                        self.log.record(Level::Error, &message);
+                    }
+                    Update::ForcedOff(name, Ok(())) => self.report(format!(
+                        "{} did not shut down within {}s, forced it off",
+                        name, self.config.shutdown_timeout
+                    )),
+                    Update::ForcedOff(name, Err(e)) => {
+                        self.set_error(format!("Failed to force off {}: {}", name, e));
+                    }
+                }
+            }
+        }
+        self.refresh_rows();
+    }
+
+    /// Asks the collectors for new samples, shown as soon as they arrive.
+    /// Never waits for libvirt, so it's cheap to call as often as needed.
+    pub fn refresh(&mut self) {
+        // Only running VMs are asked, since the agent can't answer otherwise
+        // and caps only apply to them.
+        let selected = self
+            .selected_index()
+            .map(|i| &self.session().metrics[i])
+            .filter(|domain| domain.status == DomainState::Running)
+            .map(|domain| domain.name.clone());
+        for (i, session) in self.sessions.iter().enumerate() {
+            let selected = selected.clone().filter(|_| i == self.current);
+            session
+                .collector
+                .request(selected, self.show_inactive, self.events.clone());
+        }
+    }
+
+    /// Recomputes which rows are shown from the current filter and keeps the
+    /// selection and scrollbar inside the (possibly shrunk or grown) list.
+    pub fn refresh_rows(&mut self) {
+        for session in &mut self.sessions {
+            session.refresh_rows(
+                &self.filter,
+                self.state_filter,
+                self.sort_column,
+                self.sort_descending,
+                self.grouped.then_some(self.collapsed.as_slice()),
+            );
+        }
+    }
+
+    /// Switches to the next color palette.
+    pub fn next_color(&mut self) {
+        self.color_index = (self.color_index + 1) % PALETTES.len();
+        self.colors = TableColors::new(&PALETTES[self.color_index]);
+    }
+
+    /// Switches between padded and single line table rows, and saves the
+    /// choice to the config file.
+    pub fn toggle_density(&mut self) {
+        self.density = self.density.toggle();
+        self.config.density = self.density;
+
+        match Config::store("density", self.density.name()) {
+            Ok(()) => self.set_status(format!("Showing {} rows", self.density.name())),
+            Err(e) => self.set_error(format!("Could not save the row density: {}", e)),
+        }
+    }
+
+    /// Switches to the next units byte counts are shown in, and saves the
+    /// choice to the config file.
+    pub fn cycle_units(&mut self) {
+        self.units = self.units.cycle();
+        self.config.units = self.units;
+        for session in &mut self.sessions {
+            session.format_table(self.units, self.per_vcpu);
+        }
+        self.refresh_rows();
+
+        match Config::store("units", self.units.name()) {
+            Ok(()) => self.set_status(format!("Showing sizes in {} units", self.units.name())),
+            Err(e) => self.set_error(format!("Could not save the units: {}", e)),
+        }
+    }
+
+    /// Switches between CPU usage summed over all vCPUs and divided by their
+    /// count.
+    pub fn toggle_per_vcpu(&mut self) {
+        self.per_vcpu = !self.per_vcpu;
+        for session in &mut self.sessions {
+            session.format_table(self.units, self.per_vcpu);
+        }
+        self.refresh_rows();
+
+        if self.per_vcpu {
+            self.set_status(String::from("Showing cpu usage per vcpu"));
+        } else {
+            self.set_status(String::from("Showing cpu usage summed over all vcpus"));
+        }
+    }
+
+    /// Moves the focus to the other pane.
+    pub fn toggle_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Table => Focus::Overview,
+            Focus::Overview => Focus::Table,
+        };
+        match self.focus {
+            Focus::Table => self.set_status(String::from("Keys move through the table")),
+            Focus::Overview => self.set_status(String::from("Keys scroll the overview")),
+        }
+    }
+
+    /// Switches between listing all VMs and only the ones that are running,
+    /// and asks for a sample to match.
+    pub fn toggle_inactive(&mut self) {
+        self.show_inactive = !self.show_inactive;
+        self.refresh();
+
+        if self.show_inactive {
+            self.set_status(String::from("Showing shut off vms"));
+        } else {
+            self.set_status(String::from("Hiding shut off vms"));
+        }
+    }
+
+    /// Shows `column` if it is hidden and hides it otherwise, and saves the
+    /// choice to the config file. The last column shown can't be hidden.
+    pub fn toggle_column(&mut self, column: Column) {
+        if self.columns == [column] {
+            self.set_error(String::from("At least one column has to be shown"));
+            return;
+        }
+        let shown = !self.columns.contains(&column);
+        self.columns = Column::ALL
+            .into_iter()
+            .filter(|&c| {
+                if c == column {
+                    shown
                 } else {
-                    String::from("off")
-                },
+                    self.columns.contains(&c)
+                }
             })
+            .collect();
+        self.config.columns = Column::names(&self.columns);
+
+        if let Err(e) = Config::store("columns", &self.config.columns) {
+            self.set_error(format!("Could not save the columns: {}", e));
         }
+    }
 
-        self.table_data = table_data;
+    /// Shows the VMs in the next set of states in turn.
+    pub fn cycle_state_filter(&mut self) {
+        self.state_filter = self.state_filter.cycle();
+        self.refresh_rows();
+    }
+
+    /// Defines the VM again from the XML the user edited, if it changed and
+    /// is well-formed. Libvirt's reason for rejecting it is reported as is.
+    pub fn finish_edit(&mut self, edit: XmlEdit, edited: AppResult<String>) {
+        let name = edit.vm_name;
+        let edited = match edited {
+            Ok(edited) => edited,
+            Err(e) => {
+                self.set_error(format!("Editing {} failed: {}", name, e));
+                return;
+            }
+        };
+        if edited == edit.xml {
+            self.set_status(format!("{} left unchanged", name));
+            return;
+        }
+        if let Err(e) = check_xml(&edited) {
+            self.set_error(format!("Not redefining {}, invalid XML: {}", name, e));
+            return;
+        }
+
+        if self.skip_in_dry_run(format!("Redefined {}", name)) {
+            return;
+        }
+        let Some(conn) = self.conn() else {
+            self.set_error(format!("Not redefining {}, not connected", name));
+            return;
+        };
+        match redefine(conn, &edited) {
+            Ok(()) => {
+                // The collector would keep showing the old title and
+                // description.
+                let session = self.session();
+                if let Some(domain) = session.metrics.iter().find(|m| m.name == name) {
+                    session.collector.reread_xml(Some(domain.uuid.clone()));
+                }
+                self.report(format!("Redefined {}", name));
+            }
+            Err(e) => self.set_error(e.to_string()),
+        }
+        self.refresh();
+    }
+
+    /// Handles a VM changing state or being (re)defined: the XML of the VMs
+    /// is read again, as it may have changed, and the table refreshed unless
+    /// it is frozen.
+    pub fn domains_changed(&mut self) {
+        for session in &self.sessions {
+            session.collector.reread_xml(None);
+        }
+        if self.auto_refresh {
+            self.refresh();
+        }
+    }
+
+    /// Switches between a flat table and one grouped by state.
+    pub fn toggle_grouping(&mut self) {
+        self.grouped = !self.grouped;
+        self.refresh_rows();
+    }
+
+    /// Collapses or expands the group of the selected row.
+    pub fn toggle_group(&mut self) {
+        if !self.grouped {
+            return;
+        }
+        let Some(group) = self.session().selected_group() else {
+            return;
+        };
+        match self.collapsed.iter().position(|&g| g == group) {
+            Some(i) => {
+                self.collapsed.remove(i);
+            }
+            None => self.collapsed.push(group),
+        }
+        self.refresh_rows();
+    }
+
+    /// Sorts by the next column in turn.
+    pub fn cycle_sort_column(&mut self) {
+        self.sort_column = SortColumn::cycle(self.sort_column);
+        self.refresh_rows();
+    }
+
+    /// Flips between ascending and descending order.
+    pub fn toggle_sort_direction(&mut self) {
+        self.sort_descending = !self.sort_descending;
+        self.refresh_rows();
+    }
+
+    /// Returns the index into `table_data`/`metrics` of the selected row.
+    pub fn selected_index(&self) -> Option<usize> {
+        self.session().selected_index()
+    }
+
+    /// Returns the currently selected row, or `None` when there are no VMs.
+    pub fn selected_item(&self) -> Option<&TableData> {
+        self.selected_index().map(|i| &self.session().table_data[i])
+    }
+
+    /// Asks the user to confirm `action` on the selected VM before running it.
+    pub fn confirm(&mut self, action: VmAction) {
+        if let Some(item) = self.selected_item() {
+            self.mode = AppMode::Confirm {
+                action,
+                vm_name: item.name.clone(),
+            };
+        }
+    }
+
+    /// Marks the selected VM for a batch action, or unmarks it, and moves on
+    /// to the next row.
+    pub fn toggle_mark(&mut self) {
+        let Some(index) = self.selected_index() else {
+            return;
+        };
+        let uuid = self.session().metrics[index].uuid.clone();
+        if !self.marked.remove(&uuid) {
+            self.marked.insert(uuid);
+        }
+        self.next();
+    }
+
+    /// Asks the user to confirm a batch of the actions `action_for` picks
+    /// for each marked VM, skipping those it gives `None` for.
+    pub fn confirm_batch(&mut self, action_for: impl Fn(&VmMetrics) -> Option<VmAction>) {
+        let batch: Vec<_> = self
+            .session()
+            .metrics
+            .iter()
+            .filter(|m| self.marked.contains(&m.uuid))
+            .filter_map(|m| Some((action_for(m)?, m.name.clone())))
+            .collect();
+        if batch.is_empty() {
+            self.set_error(String::from("The action applies to none of the marked VMs"));
+        } else {
+            self.mode = AppMode::ConfirmBatch(batch);
+        }
     }
 
     pub fn next(&mut self) {
-        let i = match self.table_state.selected() {
+        let wrap_around = self.config.wrap_around;
+        let session = self.session_mut();
+        if session.rows.is_empty() {
+            return;
+        }
+        let i = match session.table_state.selected() {
             Some(i) => {
-                if i >= self.table_data.len() - 1 {
-                    0
+                if i >= session.rows.len() - 1 {
+                    if wrap_around {
+                        0
+                    } else {
+                        i
+                    }
                 } else {
                     i + 1
                 }
             }
             None => 0,
         };
-        self.table_state.select(Some(i));
-        self.scroll_state = self.scroll_state.position(i * ITEM_HEIGHT);
+        session.select(i);
     }
 
     pub fn prev(&mut self) {
-        let i = match self.table_state.selected() {
+        let wrap_around = self.config.wrap_around;
+        let session = self.session_mut();
+        if session.rows.is_empty() {
+            return;
+        }
+        let i = match session.table_state.selected() {
             Some(i) => {
                 if i == 0 {
-                    self.table_data.len() - 1
+                    if wrap_around {
+                        session.rows.len() - 1
+                    } else {
+                        0
+                    }
                 } else {
                     i - 1
                 }
             }
             None => 0,
         };
-        self.table_state.select(Some(i));
-        self.scroll_state = self.scroll_state.position(i * ITEM_HEIGHT);
+        session.select(i);
+    }
+
+    /// Moves the selection down by one page, wrapping to the top from the
+    /// last row unless wraparound is turned off.
+    pub fn next_page(&mut self) {
+        let page_size = self.page_size;
+        let wrap_around = self.config.wrap_around;
+        let session = self.session_mut();
+        let last = session.rows.len().saturating_sub(1);
+        let i = match session.table_state.selected() {
+            Some(i) if i >= last && wrap_around => 0,
+            Some(i) => (i + page_size).min(last),
+            None => 0,
+        };
+        session.select(i);
+    }
+
+    /// Moves the selection up by one page, wrapping to the bottom from the
+    /// first row unless wraparound is turned off.
+    pub fn prev_page(&mut self) {
+        let page_size = self.page_size;
+        let wrap_around = self.config.wrap_around;
+        let session = self.session_mut();
+        let i = match session.table_state.selected() {
+            Some(0) if wrap_around => session.rows.len().saturating_sub(1),
+            Some(i) => i.saturating_sub(page_size),
+            None => 0,
+        };
+        session.select(i);
+    }
+
+    /// Moves the selection by `delta` rows, stopping at either end.
+    pub fn scroll(&mut self, delta: isize) {
+        let session = self.session_mut();
+        let last = session.rows.len().saturating_sub(1);
+        let i = match session.table_state.selected() {
+            Some(i) => i.saturating_add_signed(delta).min(last),
+            None => 0,
+        };
+        session.select(i);
+    }
+
+    /// Selects the row drawn at the terminal cell `(column, row)`. Returns
+    /// whether there was a row there.
+    pub fn select_at(&mut self, column: u16, row: u16) -> bool {
+        let area = self.table_area;
+        // The first line of the table is its header.
+        if column < area.x || column >= area.right() || row <= area.y || row >= area.bottom() {
+            return false;
+        }
+
+        let row_height = self.density.row_height();
+        let session = self.session_mut();
+        let i = session.table_state.offset() + usize::from(row - area.y - 1) / row_height;
+        if i >= session.rows.len() {
+            return false;
+        }
+        session.select(i);
+        true
+    }
+
+    /// Starts selecting VMs by typing the start of their names.
+    pub fn start_jump(&mut self) {
+        self.jump_prefix.clear();
+        self.mode = AppMode::Jump;
+    }
+
+    /// Adds `c` to the name typed so far, starting over after a pause of
+    /// [`JUMP_TIMEOUT`], and selects the first VM whose name starts with it.
+    pub fn jump(&mut self, c: char) {
+        if self.jump_typed_at.elapsed() >= JUMP_TIMEOUT {
+            self.jump_prefix.clear();
+        }
+        self.jump_prefix.push(c);
+        self.jump_typed_at = Instant::now();
+
+        let prefix = self.jump_prefix.to_lowercase();
+        let session = self.session_mut();
+        let found = session.rows.iter().position(|&row| match row {
+            TableRow::Vm(i) => session.table_data[i]
+                .name
+                .to_lowercase()
+                .starts_with(&prefix),
+            TableRow::Group { .. } => false,
+        });
+        match found {
+            Some(i) => session.select(i),
+            None => self.set_error(format!("No vm name starts with {}", self.jump_prefix)),
+        }
+    }
+
+    /// Selects the first row.
+    pub fn first(&mut self) {
+        self.session_mut().select(0);
+    }
+
+    /// Selects the last row.
+    pub fn last(&mut self) {
+        let session = self.session_mut();
+        session.select(session.rows.len().saturating_sub(1));
+    }
+
+    /// Writes the metrics of the VMs shown in the table, in table order, to a
+    /// file in `format` and reports where in the status area.
+    pub fn export(&mut self, format: ExportFormat) {
+        let path = match format {
+            ExportFormat::Csv => self.csv_path.clone(),
+            ExportFormat::Json => self.json_path.clone(),
+        };
+        let path = path.unwrap_or_else(|| {
+            PathBuf::from(format!(
+                "vmgr-{}.{}",
+                Local::now().format("%Y%m%d-%H%M%S"),
+                format.extension()
+            ))
+        });
+        let session = self.session();
+        let metrics = session.rows.iter().filter_map(|row| match row {
+            TableRow::Vm(i) => Some(&session.metrics[*i]),
+            TableRow::Group { .. } => None,
+        });
+
+        match format.write(&path, metrics) {
+            Ok(()) => self.set_status(format!("Exported metrics to {}", path.display())),
+            Err(e) => self.set_error(format!("Export to {} failed: {}", path.display(), e)),
+        }
+    }
+
+    /// Saves a screenshot of the running VM `name` to a timestamped file in
+    /// the working directory and reports where in the status area.
+    pub fn save_screenshot(&mut self, name: &str) {
+        let Some(conn) = self.conn() else {
+            self.set_error(String::from(NOT_CONNECTED));
+            return;
+        };
+        let (image, mime) = match screenshot(conn, name) {
+            Ok(screenshot) => screenshot,
+            Err(e) => {
+                self.set_error(format!(
+                    "Screenshot failed, does {} have a graphics device? {}",
+                    name, e
+                ));
+                return;
+            }
+        };
+
+        let extension = match mime.as_str() {
+            "image/png" => "png",
+            "image/x-portable-pixmap" => "ppm",
+            _ => "img",
+        };
+        let path = PathBuf::from(format!(
+            "vmgr-{}-{}.{}",
+            name,
+            Local::now().format("%Y%m%d-%H%M%S"),
+            extension
+        ));
+        match fs::write(&path, image) {
+            Ok(()) => self.report(format!("Saved screenshot to {}", path.display())),
+            Err(e) => self.set_error(format!("Writing {} failed: {}", path.display(), e)),
+        }
+    }
+
+    /// Opens the graphical console of the running VM `name` in the viewer
+    /// from the config, e.g. `remote-viewer spice://host:5900`.
+    pub fn open_viewer(&mut self, name: &str) {
+        let Some(conn) = self.conn() else {
+            self.set_error(String::from(NOT_CONNECTED));
+            return;
+        };
+        let graphics = match graphics(conn, name) {
+            Ok(Some(graphics)) => graphics,
+            Ok(None) => {
+                self.set_error(format!("{} has no spice or vnc display", name));
+                return;
+            }
+            Err(e) => {
+                self.set_error(format!("Failed to read the displays of {}: {}", name, e));
+                return;
+            }
+        };
+
+        // A display listening on all addresses is reached through the host
+        // of the connection.
+        let host = graphics
+            .listen
+            .unwrap_or_else(|| uri_host(&self.session().uri));
+        let host = if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host
+        };
+        let uri = format!("{}://{}:{}", graphics.kind, host, graphics.port);
+
+        // The viewer may come with arguments, e.g. `virt-viewer --full-screen`.
+        let mut words = self.config.viewer.split_whitespace();
+        let Some(program) = words.next() else {
+            self.set_error(String::from("No viewer set in the config"));
+            return;
+        };
+        let spawned = Command::new(program)
+            .args(words)
+            .arg(&uri)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match spawned {
+            Ok(_) => self.report(format!("Opened {} in {}", uri, program)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.set_error(format!(
+                "{} not found, set viewer in the config to a spice/vnc viewer",
+                program
+            )),
+            Err(e) => self.set_error(format!("Failed to run {}: {}", program, e)),
+        }
+    }
+
+    /// Attaches to the serial console of the running VM `name` and shows it.
+    pub fn open_console(&mut self, name: &str) {
+        let uri = self.session().uri.clone();
+        self.console = Some(Console::open(&uri, name, self.events.clone()));
+        self.mode = AppMode::Console;
+    }
+
+    /// Detaches from the serial console and goes back to the VM list.
+    pub fn close_console(&mut self) {
+        if let Some(console) = self.console.take() {
+            self.report(format!("Detached from the console of {}", console.vm_name));
+        }
+        self.mode = AppMode::Normal;
+    }
+
+    /// Takes in the output of the serial console, if one is attached.
+    pub fn poll_console(&mut self) {
+        if let Some(console) = &mut self.console {
+            console.poll();
+        }
+    }
+
+    /// Shows `message` in the status area for [`STATUS_TIMEOUT`].
+    pub fn set_status(&mut self, message: String) {
+        self.status_message = Some((message, Instant::now()));
+        self.status_is_error = false;
+    }
+
+    /// Shows `message` like [`App::set_status`] and records it in the log,
+    /// for connection events and actions on VMs. Toggles of the view are
+    /// left out so they don't bury those.
+    pub fn report(&mut self, message: String) {
+        self.log.record(Level::Info, &message);
+        self.set_status(message);
+    }
+
+    /// In a dry run, reports `done` as what a change would have done and
+    /// returns true for the caller to skip making it. Every change to a VM
+    /// goes through here right before its libvirt call.
+    pub fn skip_in_dry_run(&mut self, done: String) -> bool {
+        if self.dry_run {
+            self.report(format!("{} (dry run)", done));
+        }
+        self.dry_run
+    }
+
+    /// Shows `message` as a failure in the status area for [`STATUS_TIMEOUT`].
+    pub fn set_error(&mut self, message: String) {
+        self.log.record(Level::Error, &message);
+        self.status_message = Some((message, Instant::now()));
+        self.status_is_error = true;
+    }
+
+    /// Clears the status message once it has been shown long enough.
+    pub fn expire_status(&mut self) {
+        if let Some((_, set_at)) = self.status_message {
+            if set_at.elapsed() >= STATUS_TIMEOUT {
+                self.status_message = None;
+            }
+        }
     }
 
     /// Set running to false to quit the application.
     pub fn quit(&mut self) {
-        disconnect(&mut self.conn);
+        for session in &mut self.sessions {
+            if let Some(conn) = &mut session.conn {
+                disconnect(conn);
+            }
+        }
         self.running = false;
     }
 }
 
-fn constraint_len_calculator(items: &[TableData]) -> (u16, u16, u16, u16, u16) {
-    let id_len = items
-        .iter()
-        .map(TableData::id)
-        .map(UnicodeWidthStr::width)
-        .max()
-        .unwrap_or(0);
-    let name_len = items
-        .iter()
-        .map(TableData::name)
-        .map(UnicodeWidthStr::width)
-        .max()
-        .unwrap_or(0);
-    let cpu_len = items
-        .iter()
-        .map(TableData::cpu_usage)
-        .flat_map(str::lines)
-        .map(UnicodeWidthStr::width)
-        .max()
-        .unwrap_or(0);
-    let mem_len = items
-        .iter()
-        .map(TableData::mem_usage)
-        .map(UnicodeWidthStr::width)
-        .max()
-        .unwrap_or(0);
-    let status_len = items
-        .iter()
-        .map(TableData::status)
-        .map(UnicodeWidthStr::width)
-        .max()
-        .unwrap_or(0);
+/// Host part of a connection URI, e.g. `host` for `qemu+ssh://user@host/system`,
+/// or `localhost` for a local connection.
+fn uri_host(uri: &str) -> String {
+    let authority = uri
+        .split_once("://")
+        .map_or("", |(_, rest)| rest.split('/').next().unwrap_or_default());
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    // Strip a port, but not the colons of a bracketed IPv6 address.
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => host,
+        _ => host,
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        String::from("localhost")
+    } else {
+        host.to_string()
+    }
+}
+
+/// Memory column text: what the guest uses out of what it is configured
+/// with, e.g. `512.00 MiB / 2.00 GiB`.
+fn mem_text(domain: &VmMetrics, units: Units) -> String {
+    let used = units.format(domain.mem_usage() * 1024);
+    if domain.mem_max == 0 {
+        return used;
+    }
+    format!("{} / {}", used, units.format(domain.mem_max * 1024))
+}
+
+/// Appends `value` to `history`, dropping the oldest samples beyond
+/// [`HISTORY_LEN`].
+fn push_sample(history: &mut VecDeque<u64>, value: u64) {
+    if history.len() >= HISTORY_LEN {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
+
+/// Text of the status column, noting a pending managed save image.
+fn status_text(domain: &VmMetrics) -> String {
+    if domain.managed_save {
+        format!("{} (saved)", domain.status)
+    } else {
+        domain.status.to_string()
+    }
+}
 
+fn constraint_len_calculator(items: &[TableData]) -> [u16; Column::ALL.len()] {
     #[allow(clippy::cast_possible_truncation)]
-    return (
-        id_len as u16,
-        name_len as u16,
-        cpu_len as u16,
-        mem_len as u16,
-        status_len as u16,
-    );
+    Column::ALL.map(|column| {
+        items
+            .iter()
+            .map(|data| data.cell(column))
+            .flat_map(str::lines)
+            .map(UnicodeWidthStr::width)
+            .max()
+            .unwrap_or(0) as u16
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A session listing a VM for each of `names`, without a connection.
+    fn session(names: &[&str]) -> Session {
+        let mut session = Session::with_collector("test:///default", Collector::idle());
+        session.metrics = names
+            .iter()
+            .map(|name| VmMetrics {
+                name: name.to_string(),
+                uuid: format!("{}-uuid", name),
+                ..VmMetrics::default()
+            })
+            .collect();
+        session.format_table(Units::default(), false);
+        session
+    }
+
+    fn refresh(session: &mut Session) {
+        session.refresh_rows("", StateFilter::All, None, false, None);
+    }
+
+    #[test]
+    fn no_vms_select_nothing() {
+        let mut session = session(&[]);
+        refresh(&mut session);
+
+        assert!(session.rows.is_empty());
+        assert_eq!(session.table_state.selected(), None);
+        assert_eq!(session.selected_index(), None);
+        assert_eq!(
+            session.scroll_state,
+            ScrollbarState::default().content_length(0).position(0)
+        );
+    }
+
+    #[test]
+    fn single_vm_is_selected() {
+        let mut session = session(&["alpha"]);
+        refresh(&mut session);
+
+        assert_eq!(session.selected_index(), Some(0));
+        assert_eq!(session.selected_uuid.as_deref(), Some("alpha-uuid"));
+        assert_eq!(
+            session.scroll_state,
+            ScrollbarState::default().content_length(1).position(0)
+        );
+    }
+
+    #[test]
+    fn last_vm_going_away_clears_selection() {
+        let mut session = session(&["alpha"]);
+        refresh(&mut session);
+
+        session.metrics.clear();
+        session.format_table(Units::default(), false);
+        refresh(&mut session);
+
+        assert_eq!(session.selected_index(), None);
+        assert_eq!(
+            session.scroll_state,
+            ScrollbarState::default().content_length(0).position(0)
+        );
+    }
+
+    #[test]
+    fn selection_is_clamped_to_shrunk_list() {
+        let mut session = session(&["alpha", "beta", "gamma"]);
+        refresh(&mut session);
+        session.table_state.select(Some(2));
+        session.selected_uuid = None;
+
+        session.metrics.truncate(1);
+        session.format_table(Units::default(), false);
+        refresh(&mut session);
+
+        assert_eq!(session.selected_index(), Some(0));
+        assert_eq!(
+            session.scroll_state,
+            ScrollbarState::default().content_length(1).position(0)
+        );
+    }
 }
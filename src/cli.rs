@@ -0,0 +1,49 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use clap::Parser;
+
+/// kvm/qemu tui-based virtual machine manager
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// Hypervisor connection URI, e.g. qemu+ssh://host/system; repeat to show several hosts as tabs [default: config file, or qemu:///system]
+    #[arg(short, long)]
+    pub connect: Vec<String>,
+
+    /// Interval between stat refreshes, in milliseconds [default: config file, or 1000]
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub refresh: Option<u64>,
+
+    /// Open a read-only connection, which disables every action that changes vms
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// Only report what actions that change vms would do, without doing it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// File metrics are exported to as CSV [default: vmgr-<timestamp>.csv in the working directory]
+    #[arg(long, value_name = "PATH")]
+    pub csv: Option<PathBuf>,
+
+    /// File metrics are exported to as JSON [default: vmgr-<timestamp>.json in the working directory]
+    #[arg(long, value_name = "PATH")]
+    pub json: Option<PathBuf>,
+
+    /// Print a table of all vms with their state, cpu and memory usage and exit, without starting the interface
+    #[arg(long)]
+    pub list: bool,
+
+    /// Print the metrics of all vms as JSON and exit, without starting the interface
+    #[arg(long)]
+    pub dump_json: bool,
+
+    /// Serve the metrics of all vms for Prometheus on this port instead of starting the interface
+    #[arg(long, value_name = "PORT")]
+    pub metrics_port: Option<u16>,
+
+    /// Address the metrics of --metrics-port are served on, e.g. 0.0.0.0 to serve them to other hosts
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1")]
+    pub metrics_addr: IpAddr,
+}
@@ -0,0 +1,384 @@
+use std::collections::{HashMap, HashSet};
+use std::iter;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use virt::connect::Connect;
+use virt::error::Error;
+
+use crate::event::Event;
+use crate::vms::{
+    connect, connect_error, connect_read_only, disconnect, force_stop, get_host_info, get_vm_data,
+    guest_addresses, host_cpu_times, host_memory, io_limits, vcpu_pins, xml_info, GuestAddress,
+    HostCpuTimes, HostInfo, HostMemory, IoLimits, Metadata, OsInfo, VmMetrics,
+};
+
+/// How often a lost connection is reopened.
+pub const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time between a baseline sample and the sample it is compared against,
+/// for VMs the collector hasn't seen yet.
+const BASELINE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Guest agent addresses of the named VM, or `None` when its agent couldn't
+/// be reached.
+pub type GuestAddresses = (String, Option<Vec<GuestAddress>>);
+
+/// Stats gathered by the [`Collector`] in answer to one request.
+#[derive(Debug)]
+pub struct Sample {
+    pub metrics: Vec<VmMetrics>,
+    /// An earlier sample taken just before `metrics`, when running VMs
+    /// turned up that there was no previous sample of. Their CPU usage would
+    /// otherwise read 0% until the next sample.
+    pub baseline: Option<Vec<VmMetrics>>,
+    /// Addresses of the running VM named in the request, if any.
+    pub guest_addresses: Option<GuestAddresses>,
+    /// Bandwidth caps of the running VM named in the request, by name, if
+    /// any.
+    pub io_limits: Option<(String, IoLimits)>,
+    /// Host CPUs each vCPU of the running VM named in the request may run
+    /// on, by name, if any.
+    pub vcpu_pins: Option<(String, Vec<Vec<u32>>)>,
+    /// CPU times of the host, unless the driver doesn't report them.
+    pub host_cpu: Option<HostCpuTimes>,
+    /// Memory usage of the host, unless the driver doesn't report it.
+    pub host_memory: Option<HostMemory>,
+}
+
+/// The connection actions go through, opened by the collector so the
+/// interface never waits for the hypervisor, and the host it reaches.
+#[derive(Debug)]
+pub struct Handoff {
+    pub conn: Connect,
+    pub host: HostInfo,
+}
+
+// SAFETY: libvirt connections may be used from any thread. The collector
+// thread never touches this one after sending it, so it is only ever used by
+// one thread at a time.
+unsafe impl Send for Handoff {}
+
+/// What the collector thread is asked to do.
+#[derive(Debug)]
+enum Request {
+    Sample(SampleRequest),
+    /// Force off the named VM, which ignored a graceful shutdown.
+    ForceOff(String),
+    /// Read the XML of the domain with this UUID again, or of every domain
+    /// if `None`, as it may have been redefined.
+    RereadXml(Option<String>),
+}
+
+/// A request for a sample.
+#[derive(Debug)]
+struct SampleRequest {
+    /// Running VM to query the guest agent, bandwidth caps and vCPU pinning
+    /// of.
+    selected: Option<String>,
+    /// Are shut off VMs sampled too?
+    inactive: bool,
+    /// Woken with [`Event::Sample`] once the answer is ready.
+    wake: Option<Sender<Event>>,
+}
+
+/// What the [`Collector`] has to say in answer to a request.
+#[derive(Debug)]
+pub enum Update {
+    /// The collector got its first connection, or got it back, and opened
+    /// the one actions go through alongside it. Sent just before the first
+    /// sample over it.
+    Connected(Box<Handoff>),
+    Sample(Box<Sample>),
+    /// The connection to the hypervisor was lost, with the reason. The
+    /// collector keeps trying to reopen it, and sends samples again once
+    /// it succeeds.
+    Disconnected(String),
+    /// Taking a sample failed although the connection is alive, with the
+    /// reason. The next request tries again.
+    Failed(String),
+    /// The named VM was forced off after ignoring a graceful shutdown, or
+    /// why that failed.
+    ForcedOff(String, Result<(), String>),
+}
+
+/// Gathers stats on a background thread with its own hypervisor connection,
+/// so slow libvirt calls never hold up input handling or drawing.
+#[derive(Debug)]
+pub struct Collector {
+    requests: Sender<Request>,
+    updates: Receiver<Update>,
+}
+
+impl Collector {
+    /// Starts the collector thread, which connects to the hypervisor at
+    /// `uri`, read-only if asked to. A failure to connect is reported as an
+    /// [`Update::Disconnected`] and retried like a lost connection.
+    pub fn spawn(uri: &str, read_only: bool) -> Self {
+        let (request_tx, request_rx) = mpsc::channel::<Request>();
+        let (update_tx, update_rx) = mpsc::channel();
+        let uri = uri.to_string();
+
+        // A `Connect` can't move between threads, so it is opened on the
+        // collector thread.
+        thread::spawn(move || {
+            let mut last_attempt = Instant::now();
+            // Domain XML is only read once per domain, by UUID, unless asked
+            // to read it again.
+            let mut xml_cache = HashMap::new();
+            // UUIDs of the domains in the last sample.
+            let mut seen = HashSet::new();
+            // Connection for actions, handed over with the next sample.
+            let mut handoff = None;
+            let mut conn = match open(&uri, read_only) {
+                Ok((conn, actions)) => {
+                    handoff = Some(actions);
+                    Some(conn)
+                }
+                Err(e) => {
+                    if update_tx
+                        .send(Update::Disconnected(connect_error(&uri, &e)))
+                        .is_err()
+                    {
+                        return;
+                    }
+                    None
+                }
+            };
+
+            while let Ok(request) = request_rx.recv() {
+                // Force-offs are all run, but sample requests that piled up
+                // during a slow collection are served by a single one.
+                let mut sample = None;
+                for request in iter::once(request).chain(request_rx.try_iter()) {
+                    match request {
+                        Request::Sample(request) => sample = Some(request),
+                        Request::ForceOff(name) => {
+                            let result = match &conn {
+                                Some(c) => force_stop(c, &name).map_err(|e| e.to_string()),
+                                None => Err(String::from("not connected")),
+                            };
+                            let _ = update_tx.send(Update::ForcedOff(name, result));
+                        }
+                        Request::RereadXml(Some(uuid)) => {
+                            xml_cache.remove(&uuid);
+                        }
+                        Request::RereadXml(None) => xml_cache.clear(),
+                    }
+                }
+                let Some(request) = sample else {
+                    continue;
+                };
+
+                if conn.is_none() {
+                    if last_attempt.elapsed() < RECONNECT_INTERVAL {
+                        continue;
+                    }
+                    last_attempt = Instant::now();
+                    if let Ok((c, actions)) = open(&uri, read_only) {
+                        conn = Some(c);
+                        handoff = Some(actions);
+                    }
+                }
+                let Some(c) = &conn else {
+                    continue;
+                };
+
+                let update = match collect(
+                    c,
+                    request.selected,
+                    request.inactive,
+                    read_only,
+                    &mut xml_cache,
+                    &mut seen,
+                ) {
+                    Ok(sample) => {
+                        if let Some(actions) = handoff.take() {
+                            if update_tx
+                                .send(Update::Connected(Box::new(actions)))
+                                .is_err()
+                            {
+                                break;
+                            }
+                        }
+                        Update::Sample(Box::new(sample))
+                    }
+                    Err(e) => {
+                        // Errors on a live connection are passing; only a
+                        // dead one is given up and reopened.
+                        if c.is_alive().unwrap_or(false) {
+                            if update_tx.send(Update::Failed(e.to_string())).is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        if let Some(mut lost) = conn.take() {
+                            let _ = lost.close();
+                        }
+                        if let Some(mut unused) = handoff.take() {
+                            let _ = unused.conn.close();
+                        }
+                        last_attempt = Instant::now();
+                        Update::Disconnected(e.to_string())
+                    }
+                };
+                if update_tx.send(update).is_err() {
+                    break;
+                }
+                if let Some(wake) = request.wake {
+                    let _ = wake.send(Event::Sample);
+                }
+            }
+            if let Some(mut conn) = conn {
+                disconnect(&mut conn);
+            }
+            if let Some(mut unused) = handoff {
+                disconnect(&mut unused.conn);
+            }
+        });
+
+        Self {
+            requests: request_tx,
+            updates: update_rx,
+        }
+    }
+
+    /// A collector without a thread behind it, for tests that have no
+    /// hypervisor to connect to. Requests go unanswered.
+    #[cfg(test)]
+    pub fn idle() -> Self {
+        let (requests, _) = mpsc::channel();
+        let (_, updates) = mpsc::channel();
+        Self { requests, updates }
+    }
+
+    /// Asks for a new sample, querying the guest agent, bandwidth caps and
+    /// vCPU pinning of the running VM `selected` too.
+    /// Returns right away; the answer is picked up by
+    /// [`Collector::updates`], and `wake` is sent an [`Event::Sample`] when
+    /// it is ready.
+    pub fn request(&self, selected: Option<String>, inactive: bool, wake: Option<Sender<Event>>) {
+        let _ = self.requests.send(Request::Sample(SampleRequest {
+            selected,
+            inactive,
+            wake,
+        }));
+    }
+
+    /// Forces off the VM `name` over the collector's connection, so a slow
+    /// hypervisor doesn't hold up the interface. The outcome arrives as an
+    /// [`Update::ForcedOff`].
+    pub fn force_off(&self, name: String) {
+        let _ = self.requests.send(Request::ForceOff(name));
+    }
+
+    /// Has the title, description and OS of the domain with UUID `uuid`, or
+    /// of every domain if `None`, read from its XML again with the next
+    /// sample.
+    pub fn reread_xml(&self, uuid: Option<String>) {
+        let _ = self.requests.send(Request::RereadXml(uuid));
+    }
+
+    /// Returns the updates that arrived since the last call, oldest first,
+    /// without waiting. A sample is left out if a newer one arrived. Fails
+    /// once the collector thread is gone.
+    pub fn updates(&self) -> Result<Vec<Update>, TryRecvError> {
+        let mut updates = vec![];
+        loop {
+            match self.updates.try_recv() {
+                Ok(update) => {
+                    if matches!(update, Update::Sample(_)) {
+                        updates.retain(|u| !matches!(u, Update::Sample(_)));
+                    }
+                    updates.push(update);
+                }
+                Err(TryRecvError::Empty) => return Ok(updates),
+                Err(e) if updates.is_empty() => return Err(e),
+                Err(_) => return Ok(updates),
+            }
+        }
+    }
+}
+
+/// Opens the collector's connection to the hypervisor at `uri`, read-only if
+/// asked to, and a second one for actions along with the host it reaches.
+fn open(uri: &str, read_only: bool) -> Result<(Connect, Handoff), Error> {
+    let open_one = || {
+        if read_only {
+            connect_read_only(uri)
+        } else {
+            connect(uri)
+        }
+    };
+    let mut conn = open_one()?;
+    let actions = open_one().and_then(|mut actions| match get_host_info(&actions) {
+        Ok(host) => Ok(Handoff {
+            conn: actions,
+            host,
+        }),
+        Err(e) => {
+            let _ = actions.close();
+            Err(e)
+        }
+    });
+    match actions {
+        Ok(actions) => Ok((conn, actions)),
+        Err(e) => {
+            let _ = conn.close();
+            Err(e)
+        }
+    }
+}
+
+/// Samples the VMs over `conn`. The guest agent of `selected` isn't asked
+/// over a `read_only` connection, which libvirt refuses.
+fn collect(
+    conn: &Connect,
+    selected: Option<String>,
+    inactive: bool,
+    read_only: bool,
+    xml_cache: &mut HashMap<String, (OsInfo, Metadata)>,
+    seen: &mut HashSet<String>,
+) -> Result<Sample, Error> {
+    let mut metrics = get_vm_data(conn, inactive)?;
+    let mut baseline = None;
+    if metrics
+        .iter()
+        .any(|m| m.status.is_active() && !seen.contains(&m.uuid))
+    {
+        thread::sleep(BASELINE_INTERVAL);
+        baseline = Some(mem::replace(&mut metrics, get_vm_data(conn, inactive)?));
+    }
+    *seen = metrics.iter().map(|m| m.uuid.clone()).collect();
+
+    xml_cache.retain(|uuid, _| metrics.iter().any(|m| &m.uuid == uuid));
+    for domain in &mut metrics {
+        if !xml_cache.contains_key(&domain.uuid) {
+            let Ok(info) = xml_info(conn, &domain.uuid) else {
+                continue;
+            };
+            xml_cache.insert(domain.uuid.clone(), info);
+        }
+        (domain.os, domain.metadata) = xml_cache[&domain.uuid].clone();
+    }
+
+    let guest_addresses = selected.clone().filter(|_| !read_only).map(|name| {
+        let addresses = guest_addresses(conn, &name).ok();
+        (name, addresses)
+    });
+    let io_limits = selected
+        .clone()
+        .and_then(|name| Some((name.clone(), io_limits(conn, &name).ok()?)));
+    let vcpu_pins = selected.and_then(|name| Some((name.clone(), vcpu_pins(conn, &name).ok()?)));
+    Ok(Sample {
+        metrics,
+        baseline,
+        guest_addresses,
+        io_limits,
+        vcpu_pins,
+        host_cpu: host_cpu_times(conn).ok(),
+        host_memory: host_memory(conn).ok(),
+    })
+}
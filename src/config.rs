@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::app::{Column, Density, PALETTES};
+use crate::cli::Args;
+use crate::units::Units;
+
+/// Hypervisor connected to when neither the command line nor the config
+/// file name one.
+pub const DEFAULT_URI: &str = "qemu:///system";
+/// The per-user hypervisor, open to anyone without root or the libvirt
+/// group.
+pub const SESSION_URI: &str = "qemu:///session";
+
+/// Default interval between stat refreshes, in milliseconds.
+pub const DEFAULT_REFRESH: u64 = 1000;
+
+/// Default time a VM gets to shut down before it is forced off, when asked
+/// to, in seconds.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 60;
+
+/// User settings read from `$XDG_CONFIG_HOME/vmgr/config.toml`.
+///
+/// ```toml
+/// uri = "qemu:///session"
+/// refresh = 2000
+/// palette = 1
+/// units = "gib"
+/// density = "compact"
+/// confirm_quit = true
+/// viewer = "virt-viewer --full-screen"
+/// columns = "name cpu memory status"
+/// force_off_after_shutdown = true
+/// shutdown_timeout = 60
+/// wrap_around = false
+/// show_inactive = false
+///
+/// [keys]
+/// quit = "Q"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Hypervisor connection URI.
+    pub uri: String,
+    /// Interval between stat refreshes, in milliseconds.
+    pub refresh: u64,
+    /// Index of the color palette to start with.
+    pub palette: usize,
+    /// Units byte counts are shown in.
+    pub units: Units,
+    /// How much room each row of the table takes.
+    pub density: Density,
+    /// Ask before quitting, so a stray `q` doesn't close vmgr.
+    pub confirm_quit: bool,
+    /// Program, with arguments, graphical consoles are opened in. It is
+    /// passed a `spice://` or `vnc://` URI.
+    pub viewer: String,
+    /// Space separated names of the table columns shown.
+    pub columns: String,
+    /// Force off VMs that are still running [`Config::shutdown_timeout`]
+    /// seconds after being asked to shut down, e.g. guests ignoring ACPI.
+    pub force_off_after_shutdown: bool,
+    /// Seconds a VM gets to shut down before it is forced off.
+    pub shutdown_timeout: u64,
+    /// Does moving past the last row select the first one, and the other way
+    /// round?
+    pub wrap_around: bool,
+    /// Are shut off VMs listed at startup?
+    pub show_inactive: bool,
+    /// Key binding overrides, from action name to key.
+    pub keys: HashMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            uri: String::from(DEFAULT_URI),
+            refresh: DEFAULT_REFRESH,
+            palette: 0,
+            units: Units::default(),
+            density: Density::default(),
+            confirm_quit: false,
+            viewer: String::from("remote-viewer"),
+            columns: Column::names(&Column::ALL),
+            force_off_after_shutdown: false,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            wrap_around: true,
+            show_inactive: true,
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Location of the config file, or `None` when neither `XDG_CONFIG_HOME`
+    /// nor `HOME` is set.
+    pub fn path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("vmgr").join("config.toml"))
+    }
+
+    /// Reads the config file. A missing file gives the defaults; an invalid
+    /// one gives the defaults along with a warning saying what is wrong.
+    pub fn load() -> (Self, Option<String>) {
+        let Some(path) = Self::path() else {
+            return (Self::default(), None);
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return (Self::default(), None),
+            Err(e) => {
+                let warning = format!("Could not read {}: {}", path.display(), e);
+                return (Self::default(), Some(warning));
+            }
+        };
+
+        match toml::from_str::<Self>(&text).map_err(|e| e.to_string()) {
+            Ok(config) => match config.validate() {
+                Ok(()) => (config, None),
+                Err(e) => (Self::default(), Some(format!("{}: {}", path.display(), e))),
+            },
+            Err(e) => (
+                Self::default(),
+                Some(format!("Invalid {}: {}", path.display(), e.trim())),
+            ),
+        }
+    }
+
+    /// Sets the top-level `key` to the string `value` in the config file,
+    /// leaving the rest of the file as it is. The file is created if missing.
+    pub fn store(key: &str, value: &str) -> Result<(), String> {
+        let path = Self::path().ok_or("neither XDG_CONFIG_HOME nor HOME is set")?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("could not read {}: {}", path.display(), e)),
+        };
+
+        let setting = format!("{} = \"{}\"", key, value);
+        let mut lines: Vec<String> = text.lines().map(String::from).collect();
+        // Top-level keys have to come before the first table.
+        let tables = lines
+            .iter()
+            .position(|l| l.trim_start().starts_with('['))
+            .unwrap_or(lines.len());
+        let existing = lines[..tables]
+            .iter()
+            .position(|l| l.split_once('=').is_some_and(|(k, _)| k.trim() == key));
+        match existing {
+            Some(i) => lines[i] = setting,
+            None => lines.insert(tables, setting),
+        }
+
+        let write = |path: &PathBuf| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(path, lines.join("\n") + "\n")
+        };
+        write(&path).map_err(|e| format!("could not write {}: {}", path.display(), e))
+    }
+
+    /// Reads the config file like [`Config::load`], then applies the settings
+    /// given on the command line, which take precedence.
+    pub fn load_with_args(args: &Args) -> (Self, Option<String>) {
+        let (mut config, warning) = Self::load();
+        // Modes that talk to a single hypervisor use the first one given.
+        if let Some(uri) = args.connect.first() {
+            config.uri = uri.clone();
+        }
+        if let Some(refresh) = args.refresh {
+            config.refresh = refresh;
+        }
+        (config, warning)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.refresh == 0 {
+            return Err(String::from("refresh must be at least 1 millisecond"));
+        }
+        if self.palette >= PALETTES.len() {
+            return Err(format!(
+                "palette must be between 0 and {}",
+                PALETTES.len() - 1
+            ));
+        }
+        Ok(())
+    }
+}
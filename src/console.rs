@@ -0,0 +1,240 @@
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use virt::connect::Connect;
+use virt::error::Error;
+use virt::stream::Stream;
+
+use crate::event::Event;
+use crate::vms::{connect, open_console, stream_recv, stream_send};
+
+/// Most lines of console output kept.
+const SCROLLBACK: usize = 1000;
+
+/// How long the console thread sleeps when there is nothing to read or write.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// What the console thread sends back.
+#[derive(Debug)]
+enum Output {
+    Data(Vec<u8>),
+    /// The console stopped, with the reason.
+    Closed(String),
+}
+
+/// Where in an escape sequence the console output is, as those are skipped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    /// Right after `ESC`.
+    Esc,
+    /// Inside a control sequence (`ESC [`), up to its final byte.
+    Csi,
+}
+
+/// The serial console of a running VM, attached on a thread with its own
+/// hypervisor connection. Dropping it detaches.
+#[derive(Debug)]
+pub struct Console {
+    pub vm_name: String,
+    input: Sender<Vec<u8>>,
+    output: Receiver<Output>,
+    /// Output so far, split into lines, without escape sequences.
+    pub lines: Vec<String>,
+    escape: Escape,
+    /// Why the console stopped, once it did.
+    pub closed: Option<String>,
+}
+
+impl Console {
+    /// Attaches to the console of the running VM `vm_name` on the hypervisor
+    /// at `uri`. Returns right away; failing to attach shows up as the
+    /// console being closed. `wake` is sent an [`Event::Console`] whenever
+    /// output arrives.
+    pub fn open(uri: &str, vm_name: &str, wake: Option<Sender<Event>>) -> Self {
+        let (input_tx, input_rx) = mpsc::channel::<Vec<u8>>();
+        let (output_tx, output_rx) = mpsc::channel();
+        let uri = uri.to_string();
+        let name = vm_name.to_string();
+
+        // Like the collector, the thread opens its own connection since a
+        // `Connect` can't move between threads.
+        thread::spawn(move || {
+            let notify = || {
+                if let Some(wake) = &wake {
+                    let _ = wake.send(Event::Console);
+                }
+            };
+            let reason = match connect(&uri) {
+                Ok(mut conn) => {
+                    let reason = pump(&conn, &name, &input_rx, &output_tx, &notify);
+                    let _ = conn.close();
+                    reason
+                }
+                Err(e) => e.to_string(),
+            };
+            let _ = output_tx.send(Output::Closed(reason));
+            notify();
+        });
+
+        Self {
+            vm_name: vm_name.to_string(),
+            input: input_tx,
+            output: output_rx,
+            lines: vec![String::new()],
+            escape: Escape::None,
+            closed: None,
+        }
+    }
+
+    /// Takes in the output that arrived since the last call.
+    pub fn poll(&mut self) {
+        while let Ok(output) = self.output.try_recv() {
+            match output {
+                Output::Data(data) => self.feed(&String::from_utf8_lossy(&data)),
+                Output::Closed(reason) => self.closed = Some(reason),
+            }
+        }
+    }
+
+    /// Forwards a key press to the guest.
+    pub fn send_key(&self, key: KeyEvent) {
+        if let Some(bytes) = key_bytes(key) {
+            let _ = self.input.send(bytes);
+        }
+    }
+
+    /// Appends output to `lines`, following newlines and backspaces and
+    /// dropping escape sequences and other control characters.
+    fn feed(&mut self, text: &str) {
+        for c in text.chars() {
+            match self.escape {
+                Escape::Esc => {
+                    self.escape = if c == '[' { Escape::Csi } else { Escape::None };
+                    continue;
+                }
+                Escape::Csi => {
+                    if ('@'..='~').contains(&c) {
+                        self.escape = Escape::None;
+                    }
+                    continue;
+                }
+                Escape::None => {}
+            }
+
+            let line = self.lines.last_mut().expect("console has no lines");
+            match c {
+                '\x1b' => self.escape = Escape::Esc,
+                '\n' => self.lines.push(String::new()),
+                '\x08' => {
+                    line.pop();
+                }
+                '\t' => line.push_str("    "),
+                c if c.is_control() => {}
+                c => line.push(c),
+            }
+        }
+
+        if self.lines.len() > SCROLLBACK {
+            self.lines.drain(..self.lines.len() - SCROLLBACK);
+        }
+    }
+}
+
+/// Is `key` the `ctrl-]` that detaches from a console, as in virsh?
+pub fn is_detach(key: KeyEvent) -> bool {
+    key.code == KeyCode::Char(']') && key.modifiers.contains(KeyModifiers::CONTROL)
+}
+
+/// Bytes a terminal sends for `key`, or `None` for keys without any.
+fn key_bytes(key: KeyEvent) -> Option<Vec<u8>> {
+    let bytes = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            vec![(c.to_ascii_lowercase() as u8) & 0x1f]
+        }
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        KeyCode::Home => b"\x1b[H".to_vec(),
+        KeyCode::End => b"\x1b[F".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        KeyCode::PageUp => b"\x1b[5~".to_vec(),
+        KeyCode::PageDown => b"\x1b[6~".to_vec(),
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+/// Moves bytes between the console of `name` and the channels until either
+/// side goes away, and returns why it stopped.
+fn pump(
+    conn: &Connect,
+    name: &str,
+    input: &Receiver<Vec<u8>>,
+    output: &Sender<Output>,
+    notify: &dyn Fn(),
+) -> String {
+    let stream = match open_console(conn, name) {
+        Ok(stream) => stream,
+        Err(e) => return e.to_string(),
+    };
+
+    let mut buf = [0; 4096];
+    loop {
+        let mut idle = true;
+        match stream_recv(&stream, &mut buf) {
+            Ok(Some(0)) => return String::from("the console was closed"),
+            Ok(Some(n)) => {
+                idle = false;
+                if output.send(Output::Data(buf[..n].to_vec())).is_err() {
+                    let _ = stream.abort();
+                    return String::from("detached");
+                }
+                notify();
+            }
+            Ok(None) => {}
+            Err(e) => return e.to_string(),
+        }
+
+        loop {
+            match input.try_recv() {
+                Ok(bytes) => {
+                    idle = false;
+                    if let Err(e) = send_all(&stream, &bytes) {
+                        return e.to_string();
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                // The console was dropped, detaching.
+                Err(TryRecvError::Disconnected) => {
+                    let _ = stream.abort();
+                    return String::from("detached");
+                }
+            }
+        }
+
+        if idle {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Writes all of `data` to a non-blocking stream, waiting while it's full.
+fn send_all(stream: &Stream, mut data: &[u8]) -> Result<(), Error> {
+    while !data.is_empty() {
+        match stream_send(stream, data)? {
+            Some(n) => data = &data[n..],
+            None => thread::sleep(POLL_INTERVAL),
+        }
+    }
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use crate::app::AppResult;
 use crossterm::event::{self, Event as CrosstermEvent, KeyEvent, KeyEventKind, MouseEvent};
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -15,8 +16,24 @@ pub enum Event {
     Mouse(MouseEvent),
     /// Terminal resize.
     Resize(u16, u16),
+    /// Output arrived on an attached serial console.
+    Console,
+    /// A stats collector answered a request.
+    Sample,
+    /// A VM changed state, e.g. started or stopped.
+    Lifecycle,
+    /// Time for the next frame of an animation, e.g. the spinner shown
+    /// while connecting.
+    Frame,
 }
 
+/// Longest the handler thread waits for terminal input at once, which bounds
+/// how long [`EventHandler::pause`] takes.
+const POLL_SLICE: Duration = Duration::from_millis(50);
+
+/// Time between two [`Event::Frame`]s while animating.
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Terminal event handler.
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -27,6 +44,12 @@ pub struct EventHandler {
     receiver: mpsc::Receiver<Event>,
     /// Event handler thread.
     handler: thread::JoinHandle<()>,
+    /// Set to stop reading the terminal, e.g. while an editor runs in it.
+    paused: Arc<AtomicBool>,
+    /// Set by the handler thread once it stopped reading.
+    idle: Arc<AtomicBool>,
+    /// Set while something on screen animates, to send frames.
+    animating: Arc<AtomicBool>,
 }
 
 impl EventHandler {
@@ -34,14 +57,36 @@ impl EventHandler {
     pub fn new(tick_rate: u64) -> Self {
         let tick_rate = Duration::from_millis(tick_rate);
         let (sender, receiver) = mpsc::channel();
+        let paused = Arc::new(AtomicBool::new(false));
+        let idle = Arc::new(AtomicBool::new(false));
+        let animating = Arc::new(AtomicBool::new(false));
         let handler = {
             let sender = sender.clone();
+            let paused = paused.clone();
+            let idle = idle.clone();
+            let animating = animating.clone();
             thread::spawn(move || {
                 let mut last_tick = Instant::now();
+                let mut last_frame = Instant::now();
                 loop {
+                    if paused.load(Ordering::Acquire) {
+                        idle.store(true, Ordering::Release);
+                        thread::sleep(POLL_SLICE);
+                        last_tick = Instant::now();
+                        continue;
+                    }
+                    idle.store(false, Ordering::Release);
+
                     let timeout = tick_rate
                         .checked_sub(last_tick.elapsed())
-                        .unwrap_or(tick_rate);
+                        .unwrap_or(tick_rate)
+                        .min(POLL_SLICE);
+                    let animate = animating.load(Ordering::Acquire);
+                    let timeout = if animate {
+                        timeout.min(FRAME_INTERVAL.saturating_sub(last_frame.elapsed()))
+                    } else {
+                        timeout
+                    };
 
                     if event::poll(timeout).expect("failed to poll new events") {
                         match event::read().expect("unable to read event") {
@@ -65,6 +110,13 @@ impl EventHandler {
                         sender.send(Event::Tick).expect("failed to send tick event");
                         last_tick = Instant::now();
                     }
+
+                    if animate && last_frame.elapsed() >= FRAME_INTERVAL {
+                        sender
+                            .send(Event::Frame)
+                            .expect("failed to send frame event");
+                        last_frame = Instant::now();
+                    }
                 }
             })
         };
@@ -72,9 +124,38 @@ impl EventHandler {
             sender,
             receiver,
             handler,
+            paused,
+            idle,
+            animating,
+        }
+    }
+
+    /// Stops reading the terminal, so another program can. Returns once the
+    /// handler thread is no longer reading.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Release);
+        while !self.idle.load(Ordering::Acquire) && !self.handler.is_finished() {
+            thread::sleep(POLL_SLICE / 5);
         }
     }
 
+    /// Goes back to reading the terminal after [`EventHandler::pause`].
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Release);
+    }
+
+    /// Starts or stops sending an [`Event::Frame`] every [`FRAME_INTERVAL`],
+    /// so animations move between ticks.
+    pub fn animate(&self, on: bool) {
+        self.animating.store(on, Ordering::Release);
+    }
+
+    /// Returns a sender for events from other threads, e.g. to redraw when a
+    /// serial console has output.
+    pub fn sender(&self) -> mpsc::Sender<Event> {
+        self.sender.clone()
+    }
+
     /// Receive the next event from the handler thread.
     ///
     /// This function will always block the current thread if
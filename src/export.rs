@@ -0,0 +1,164 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::app::AppResult;
+use crate::units::Units;
+use crate::vms::{connect, connect_error, derive_rates, disconnect, get_vm_data, VmMetrics};
+
+/// File format metrics are exported in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+
+    /// Writes `metrics` to a new file at `path`.
+    pub fn write<'a>(
+        &self,
+        path: &Path,
+        metrics: impl IntoIterator<Item = &'a VmMetrics>,
+    ) -> io::Result<()> {
+        match self {
+            Self::Csv => write_csv(path, metrics),
+            Self::Json => write_json(BufWriter::new(File::create(path)?), metrics),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "name,id,status,autostart,vcpus,mem_current_kib,cpu_time_ns,\
+cpu_usage_percent,mem_rss_kib,mem_cache_kib,net_rx_bytes,net_tx_bytes,net_rx_bytes_per_sec,\
+net_tx_bytes_per_sec,disk_rd_bytes,disk_wr_bytes,disk_rd_bytes_per_sec,disk_wr_bytes_per_sec";
+
+/// Writes `metrics` to `path` as CSV, one row per VM after a header row.
+/// Values are raw numbers in the units named in the header.
+pub fn write_csv<'a>(
+    path: &Path,
+    metrics: impl IntoIterator<Item = &'a VmMetrics>,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{}", CSV_HEADER)?;
+
+    for m in metrics {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{:.2},{},{},{},{},{:.0},{:.0},{},{},{:.0},{:.0}",
+            csv_field(&m.name),
+            m.id,
+            csv_field(m.status.as_str()),
+            m.autostart,
+            m.vcpus,
+            m.mem_current,
+            m.cpu_time,
+            m.cpu_usage,
+            m.mem_rss,
+            m.mem_cache,
+            m.net_rx,
+            m.net_tx,
+            m.net_rx_rate,
+            m.net_tx_rate,
+            m.disk_rx,
+            m.disk_wx,
+            m.disk_rx_rate,
+            m.disk_wx_rate,
+        )?;
+    }
+    out.flush()
+}
+
+/// Writes `metrics` as a JSON array of objects, with raw numeric values.
+pub fn write_json<'a, W: Write>(
+    mut out: W,
+    metrics: impl IntoIterator<Item = &'a VmMetrics>,
+) -> io::Result<()> {
+    let metrics: Vec<&VmMetrics> = metrics.into_iter().collect();
+    serde_json::to_writer_pretty(&mut out, &metrics)?;
+    writeln!(out)?;
+    out.flush()
+}
+
+/// Samples the metrics of every domain on the hypervisor at `uri` twice,
+/// `interval` apart so rates can be derived, and prints them to stdout as
+/// JSON. Meant for scripts, so it never touches the terminal state.
+pub fn dump_json(uri: &str, interval: Duration) -> AppResult<()> {
+    let metrics = sample(uri, interval)?;
+    write_json(io::stdout().lock(), &metrics)?;
+    Ok(())
+}
+
+/// Samples the domains on the hypervisor at `uri` like [`dump_json`], and
+/// prints a plain table of their name, state, CPU and memory usage to
+/// stdout, with sizes in `units`.
+pub fn print_list(uri: &str, interval: Duration, units: Units) -> AppResult<()> {
+    let metrics = sample(uri, interval)?;
+    let rows: Vec<[String; 4]> = metrics
+        .iter()
+        .map(|m| {
+            [
+                m.name.clone(),
+                m.status.as_str().to_string(),
+                format!("{:.2}%", m.cpu_usage),
+                units.format(m.mem_usage() * 1024),
+            ]
+        })
+        .collect();
+
+    let header = ["NAME", "STATE", "CPU", "MEMORY"].map(String::from);
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+
+    let mut out = io::stdout().lock();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(field, width)| format!("{:<width$}", field, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Samples the metrics of every domain on the hypervisor at `uri` twice,
+/// `interval` apart, and derives the rates between the two.
+fn sample(uri: &str, interval: Duration) -> AppResult<Vec<VmMetrics>> {
+    let mut conn = connect(uri).map_err(|e| {
+        format!(
+            "Could not connect to {}: is libvirtd running?\n{}",
+            uri,
+            connect_error(uri, &e)
+        )
+    })?;
+
+    let previous = get_vm_data(&conn, true)?;
+    thread::sleep(interval);
+    let mut metrics = get_vm_data(&conn, true)?;
+    derive_rates(&mut metrics, &previous);
+    disconnect(&mut conn);
+    Ok(metrics)
+}
+
+/// Quotes a field when it contains a separator, quote or line break.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
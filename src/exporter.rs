@@ -0,0 +1,165 @@
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::app::AppResult;
+use crate::vms::{connect_read_only, get_vm_data, VmMetrics};
+
+/// Serves the metrics of every domain on the hypervisor at `uri` on
+/// `http://<addr>:<port>/metrics` in the Prometheus text format, sampling
+/// them every `interval`. Runs until the process is killed.
+pub fn serve_metrics(uri: &str, addr: IpAddr, port: u16, interval: Duration) -> AppResult<()> {
+    // Sampling never changes anything, so read access is all that is asked for.
+    let conn = connect_read_only(uri)
+        .map_err(|e| format!("Could not connect to {}: is libvirtd running?\n{}", uri, e))?;
+    let listener = TcpListener::bind((addr, port))?;
+    println!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+
+    let page = Arc::new(Mutex::new(render_metrics(&get_vm_data(&conn, true)?)));
+    {
+        let page = Arc::clone(&page);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let body = page.lock().map(|p| p.clone()).unwrap_or_default();
+                // Each client gets its own thread so one that is slow to send
+                // its request does not hold up the scrapes queued behind it.
+                // A client hanging up mid-request is no reason to stop serving.
+                thread::spawn(move || {
+                    let _ = respond(stream, &body);
+                });
+            }
+        });
+    }
+
+    // The connection stays on this thread, sampling on a timer, while the
+    // listener thread serves whatever was sampled last.
+    loop {
+        thread::sleep(interval);
+        let metrics = match get_vm_data(&conn, true) {
+            Ok(metrics) => metrics,
+            Err(e) => {
+                eprintln!("Could not sample metrics: {}", e);
+                continue;
+            }
+        };
+        if let Ok(mut page) = page.lock() {
+            *page = render_metrics(&metrics);
+        }
+    }
+}
+
+/// Answers a single HTTP request with `body` for `GET /metrics`, and 404
+/// otherwise.
+fn respond(stream: TcpStream, body: &str) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, content_type, body) = if request_line.starts_with("GET ")
+        && (path == "/metrics" || path.starts_with("/metrics?"))
+    {
+        ("200 OK", "text/plain; version=0.0.4", body)
+    } else {
+        ("404 Not Found", "text/plain", "not found, try /metrics\n")
+    };
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// A metric exported for every domain.
+struct Family {
+    name: &'static str,
+    /// Prometheus metric type, `counter` or `gauge`.
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&VmMetrics) -> f64,
+}
+
+const FAMILIES: [Family; 7] = [
+    Family {
+        name: "vmgr_cpu_seconds_total",
+        kind: "counter",
+        help: "CPU time used by the domain.",
+        value: |m| m.cpu_time as f64 / 1_000_000_000.0,
+    },
+    Family {
+        name: "vmgr_memory_rss_bytes",
+        kind: "gauge",
+        help: "Resident memory of the domain's qemu process.",
+        value: |m| (m.mem_rss * 1024) as f64,
+    },
+    Family {
+        name: "vmgr_memory_cache_bytes",
+        kind: "gauge",
+        help: "Memory the guest uses for disk caches.",
+        value: |m| (m.mem_cache * 1024) as f64,
+    },
+    Family {
+        name: "vmgr_network_receive_bytes_total",
+        kind: "counter",
+        help: "Bytes received, summed over all interfaces.",
+        value: |m| m.net_rx as f64,
+    },
+    Family {
+        name: "vmgr_network_transmit_bytes_total",
+        kind: "counter",
+        help: "Bytes transmitted, summed over all interfaces.",
+        value: |m| m.net_tx as f64,
+    },
+    Family {
+        name: "vmgr_disk_read_bytes_total",
+        kind: "counter",
+        help: "Bytes read, summed over all block devices.",
+        value: |m| m.disk_rx as f64,
+    },
+    Family {
+        name: "vmgr_disk_written_bytes_total",
+        kind: "counter",
+        help: "Bytes written, summed over all block devices.",
+        value: |m| m.disk_wx as f64,
+    },
+];
+
+/// Formats `metrics` in the Prometheus text exposition format, one series
+/// per domain labeled by its name.
+fn render_metrics(metrics: &[VmMetrics]) -> String {
+    let mut text = String::new();
+    for family in &FAMILIES {
+        let _ = writeln!(text, "# HELP {} {}", family.name, family.help);
+        let _ = writeln!(text, "# TYPE {} {}", family.name, family.kind);
+        for m in metrics {
+            let _ = writeln!(
+                text,
+                "{}{{name=\"{}\"}} {}",
+                family.name,
+                escape_label(&m.name),
+                (family.value)(m)
+            );
+        }
+    }
+    text
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
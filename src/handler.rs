@@ -1,49 +1,1209 @@
+use std::mem;
+
 use crate::{
-    app::{App, AppResult},
-    vms::{snapshot, start, stop},
+    app::{
+        App, AppMode, AppResult, Column, Focus, Form, FormField, FormKind, NetworkView,
+        StorageView, TextView, VmAction, XmlEdit, NOT_CONNECTED,
+    },
+    console::is_detach,
+    export::ExportFormat,
+    keymap::Action,
+    network::list_networks,
+    storage::{list_pools, list_volumes},
+    vms::{
+        clone_vm, define_vm, force_stop, format_cpuset, get_editable_xml, get_xml, list_snapshots,
+        managed_save, managed_save_remove, migrate, parse_cpuset, pause, pin_vcpu, reboot, resume,
+        revert_snapshot, send_ctrl_alt_del, set_autostart, set_io_limits, set_memory, set_vcpus,
+        snapshot, start, stop, undefine, DomainState, IoLimits,
+    },
 };
 
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::widgets::ListState;
+
+/// Smallest balloon target accepted, in MiB, so a typo can't starve a guest.
+const MIN_MEMORY: u64 = 128;
 
 /// Handles the key events and updates the state of [`App`].
 pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
-    match key_event.code {
-        // Exit application on `ESC` or `q`
-        KeyCode::Esc | KeyCode::Char('q') => {
+    match app.mode {
+        AppMode::Confirm { .. } | AppMode::ConfirmBatch(_) => {
+            return handle_confirm_key_events(key_event, app)
+        }
+        AppMode::ConfirmQuit => {
+            match key_event.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => app.quit(),
+                KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                    app.mode = AppMode::Normal;
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+        AppMode::Filter => return handle_filter_key_events(key_event, app),
+        AppMode::Jump => match key_event.code {
+            KeyCode::Char(c) if !key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                app.jump(c);
+                return Ok(());
+            }
+            KeyCode::Esc | KeyCode::Enter => {
+                app.mode = AppMode::Normal;
+                return Ok(());
+            }
+            // Any other key ends the jump and does what it usually does.
+            _ => app.mode = AppMode::Normal,
+        },
+        AppMode::Form(_) => return handle_form_key_events(key_event, app),
+        AppMode::Snapshots { .. } => return handle_snapshot_key_events(key_event, app),
+        AppMode::Networks(ref mut view) => {
+            match key_event.code {
+                KeyCode::Esc | KeyCode::Char('q') => app.mode = AppMode::Normal,
+                KeyCode::Up | KeyCode::Char('k') => view.scroll(-1),
+                KeyCode::Down | KeyCode::Char('j') => view.scroll(1),
+                _ => {}
+            }
+            return Ok(());
+        }
+        AppMode::Storage(_) => {
+            handle_storage_key_events(key_event, app);
+            return Ok(());
+        }
+        AppMode::Columns(_) => {
+            handle_columns_key_events(key_event, app);
+            return Ok(());
+        }
+        AppMode::Console => {
+            handle_console_key_events(key_event, app);
+            return Ok(());
+        }
+        AppMode::Text(ref mut view) => {
+            handle_text_key_events(key_event, view);
+            if key_event.code == KeyCode::Esc || key_event.code == KeyCode::Char('q') {
+                app.mode = AppMode::Normal;
+            }
+            return Ok(());
+        }
+        AppMode::Normal => {}
+    }
+
+    // Clear an active filter on `ESC` rather than quitting
+    if key_event.code == KeyCode::Esc && !app.filter.is_empty() {
+        app.filter.clear();
+        app.refresh_rows();
+        return Ok(());
+    }
+
+    let Some(action) = app.keymap.action(key_event) else {
+        return Ok(());
+    };
+    if app.read_only && action.needs_write() {
+        app.set_error(String::from("The connection is read-only"));
+        return Ok(());
+    }
+    match action {
+        Action::Quit if app.config.confirm_quit => {
+            app.mode = AppMode::ConfirmQuit;
+        }
+        Action::Quit => {
             app.quit();
         }
-        // Exit application on `Ctrl-C`
-        KeyCode::Char('c') | KeyCode::Char('C') => {
-            if key_event.modifiers == KeyModifiers::CONTROL {
-                app.quit();
+        // Navigation scrolls the overview while it has the focus
+        Action::Up
+        | Action::Down
+        | Action::PageUp
+        | Action::PageDown
+        | Action::First
+        | Action::Last
+            if app.focus == Focus::Overview =>
+        {
+            let scroll = &mut app.overview_scroll;
+            match action {
+                Action::Up => scroll.scroll_up(1),
+                Action::Down => scroll.scroll_down(1),
+                Action::PageUp => scroll.scroll_up(scroll.page_size),
+                Action::PageDown => scroll.scroll_down(scroll.page_size),
+                Action::First => scroll.scroll_to(0),
+                _ => scroll.scroll_to(usize::MAX),
             }
         }
-        // Counter handlers
-        KeyCode::Up => {
+        Action::Up => {
             app.prev();
         }
-        KeyCode::Down => {
+        Action::Down => {
             app.next();
         }
-        KeyCode::Char('x') => {
-            let current_item = &app.table_data[app.table_state.selected().unwrap()];
-            let name = &current_item.name;
-            let status = &current_item.status;
+        Action::PageUp => {
+            app.prev_page();
+        }
+        Action::PageDown => {
+            app.next_page();
+        }
+        Action::First => {
+            app.first();
+        }
+        Action::Last => {
+            app.last();
+        }
+        // Start the marked vms that are shut off and shut down the others
+        Action::StartStop if !app.marked.is_empty() => {
+            app.confirm_batch(|vm| {
+                Some(if vm.status.is_active() {
+                    VmAction::Shutdown
+                } else {
+                    VmAction::Start
+                })
+            });
+        }
+        Action::StartStop => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+
+            if !current_item.state.is_active() {
+                app.confirm(VmAction::Start);
+            } else {
+                app.confirm(VmAction::Shutdown);
+            }
+        }
+        // Force off the vm, for guests that ignore the ACPI shutdown
+        Action::ForceOff => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+
+            if current_item.state.is_active() {
+                app.confirm(VmAction::ForceOff);
+            }
+        }
+        Action::Reboot => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+
+            if current_item.state == DomainState::Running {
+                app.confirm(VmAction::Reboot);
+            }
+        }
+        // Press ctrl+alt+del in a stuck graphical guest
+        Action::CtrlAltDel => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
 
-            if status == "off" {
-                start(&app.conn, name);
+            if current_item.state == DomainState::Running {
+                app.confirm(VmAction::CtrlAltDel);
+            }
+        }
+        Action::PauseResume => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+            let name = current_item.name.clone();
+            let state = current_item.state;
+            let done = match state {
+                DomainState::Running => format!("Paused {}", name),
+                DomainState::Paused => format!("Resumed {}", name),
+                _ => return Ok(()),
+            };
+            if app.skip_in_dry_run(done.clone()) {
+                return Ok(());
+            }
+            let Some(conn) = app.conn() else {
+                app.set_error(String::from(NOT_CONNECTED));
+                return Ok(());
+            };
+
+            let result = if state == DomainState::Running {
+                pause(conn, &name).map_err(|e| format!("Pause failed: {}", e))
             } else {
-                stop(&app.conn, name);
+                resume(conn, &name).map_err(|e| format!("Resume failed: {}", e))
+            };
+            match result {
+                Ok(()) => app.report(done),
+                Err(message) => app.set_error(message),
+            }
+        }
+        // Save the memory of the vm to disk and stop it
+        Action::Save => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+
+            if current_item.state.is_active() {
+                app.confirm(VmAction::ManagedSave);
+            }
+        }
+        // Discard a saved memory image
+        Action::DiscardSave => {
+            let Some(index) = app.selected_index() else {
+                return Ok(());
+            };
+
+            if app.session().metrics[index].managed_save {
+                app.confirm(VmAction::ManagedSaveRemove);
+            }
+        }
+        // Toggle whether the vm starts along with the host
+        Action::Autostart => {
+            let Some(index) = app.selected_index() else {
+                return Ok(());
+            };
+            let name = app.session().metrics[index].name.clone();
+            let autostart = !app.session().metrics[index].autostart;
+            let verb = if autostart { "Enabled" } else { "Disabled" };
+            let done = format!("{} autostart for {}", verb, name);
+            if app.skip_in_dry_run(done.clone()) {
+                return Ok(());
+            }
+            let Some(conn) = app.conn() else {
+                app.set_error(String::from(NOT_CONNECTED));
+                return Ok(());
+            };
+
+            match set_autostart(conn, &name, autostart) {
+                Ok(()) => {
+                    app.session_mut().metrics[index].autostart = autostart;
+                    app.report(done);
+                }
+                Err(e) => app.set_error(format!("Autostart failed: {}", e)),
+            }
+        }
+        // Delete the vm, after a confirmation that can also opt into deleting its disks
+        Action::Delete => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+
+            if current_item.state.is_active() {
+                let message = format!("Stop {} before deleting it", current_item.name);
+                app.set_error(message);
+            } else {
+                app.confirm(VmAction::Undefine {
+                    remove_storage: false,
+                });
+            }
+        }
+        // Live migrate the vm to another host
+        Action::Migrate => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+
+            if current_item.state == DomainState::Running {
+                let fields = vec![FormField::new("Destination URI", "qemu+ssh://host/system")];
+                let form = Form::new(FormKind::Migrate, current_item.name.clone(), fields)
+                    .with_note("Disks have to be on storage shared with the destination.");
+                app.mode = AppMode::Form(form);
+            }
+        }
+        // Copy a shut off vm and its disks
+        Action::Clone => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+
+            if current_item.state.is_active() {
+                let message = format!("Stop {} before cloning it", current_item.name);
+                app.set_error(message);
+            } else {
+                let vm_name = current_item.name.clone();
+                let fields = vec![FormField::new("Name", &format!("{}-clone", vm_name))];
+                let form = Form::new(FormKind::Clone, vm_name, fields).with_note(
+                    "Disk images are copied within their storage pool, which can take a while.",
+                );
+                app.mode = AppMode::Form(form);
+            }
+        }
+        // Grow or shrink the memory of a running vm through its balloon
+        Action::SetMemory => {
+            let Some(index) = app.selected_index() else {
+                return Ok(());
+            };
+            let domain = &app.session().metrics[index];
+
+            if domain.status == DomainState::Running {
+                let current = (domain.mem_current / 1024).to_string();
+                let fields = vec![FormField::new("Memory (MiB)", &current)];
+                let form = Form::new(FormKind::Memory, domain.name.clone(), fields).with_note(
+                    "Needs a balloon driver in the guest, and can't exceed the maximum memory.",
+                );
+                app.mode = AppMode::Form(form);
+            }
+        }
+        // Hot plug or unplug vcpus of a running vm
+        Action::SetVcpus => {
+            let Some(index) = app.selected_index() else {
+                return Ok(());
+            };
+            let domain = &app.session().metrics[index];
+
+            if domain.status == DomainState::Running {
+                let fields = vec![FormField::new("vCPUs", &domain.vcpus.to_string())];
+                let form = Form::new(FormKind::Vcpus, domain.name.clone(), fields)
+                    .with_note("The guest has to support vCPU hotplug.");
+                app.mode = AppMode::Form(form);
+            }
+        }
+        // Cap the disk and network bandwidth of a running vm
+        Action::IoLimits => {
+            let Some(index) = app.selected_index() else {
+                return Ok(());
+            };
+            let session = app.session();
+            let domain = &session.metrics[index];
+
+            if domain.status == DomainState::Running {
+                let limits = match &session.io_limits {
+                    Some((name, limits)) if *name == domain.name => *limits,
+                    _ => IoLimits::default(),
+                };
+                let fields = vec![
+                    FormField::new(
+                        "Disk (MiB/s)",
+                        &limits.disk.map_or(0, |bytes| bytes >> 20).to_string(),
+                    ),
+                    FormField::new("Network (KiB/s)", &limits.net.unwrap_or(0).to_string()),
+                ];
+                let form = Form::new(FormKind::IoLimits, domain.name.clone(), fields)
+                    .with_note("Applies to every disk and interface; 0 lifts the limit.");
+                app.mode = AppMode::Form(form);
+            }
+        }
+        // Pin a vcpu of a running vm to host cpus
+        Action::PinVcpu => {
+            let Some(index) = app.selected_index() else {
+                return Ok(());
+            };
+            let domain = &app.session().metrics[index];
+
+            if domain.status == DomainState::Running {
+                let fields = vec![
+                    FormField::new("vCPU", "0"),
+                    FormField::new("Host CPUs", "e.g. 0-3,6"),
+                ];
+                let form = Form::new(FormKind::PinVcpu, domain.name.clone(), fields);
+                app.mode = AppMode::Form(form);
             }
         }
+        // Define a new vm
+        Action::NewVm => {
+            let fields = vec![
+                FormField::new("Name", ""),
+                FormField::new("vCPUs", "1"),
+                FormField::new("Memory (MiB)", "1024"),
+                FormField::new("Disk image", "none"),
+            ];
+            app.mode = AppMode::Form(Form::new(FormKind::NewVm, String::new(), fields));
+        }
+        Action::Snapshot | Action::FullSnapshot if !app.marked.is_empty() => {
+            let disk_only = action == Action::Snapshot;
+            app.confirm_batch(|_| Some(VmAction::Snapshot { disk_only }));
+        }
+        Action::Snapshot => {
+            open_snapshot_form(app, true);
+        }
+        Action::FullSnapshot => {
+            open_snapshot_form(app, false);
+        }
+        Action::Mark => {
+            app.toggle_mark();
+        }
+        Action::Inactive => {
+            app.toggle_inactive();
+        }
+        Action::Focus => {
+            app.toggle_focus();
+        }
+        // Browse the snapshots of the selected vm
+        Action::Snapshots => {
+            let Some(vm_name) = app.selected_item().map(|item| item.name.clone()) else {
+                return Ok(());
+            };
+            let Some(conn) = app.conn() else {
+                app.set_error(String::from(NOT_CONNECTED));
+                return Ok(());
+            };
+
+            match list_snapshots(conn, &vm_name) {
+                Ok(snapshots) => {
+                    let selected = (!snapshots.is_empty()).then_some(0);
+                    app.mode = AppMode::Snapshots {
+                        vm_name,
+                        snapshots,
+                        list_state: ListState::default().with_selected(selected),
+                    };
+                }
+                Err(e) => app.set_error(format!(
+                    "Could not list the snapshots of {}: {}",
+                    vm_name, e
+                )),
+            }
+        }
+        // Browse the storage pools of the current host
+        Action::Storage => {
+            let Some(conn) = app.conn() else {
+                app.set_error(String::from(NOT_CONNECTED));
+                return Ok(());
+            };
+
+            match list_pools(conn) {
+                Ok(pools) => app.mode = AppMode::Storage(StorageView::new(pools)),
+                Err(e) => app.set_error(format!("Failed to list storage pools: {}", e)),
+            }
+        }
+        // List the virtual networks of the current host
+        Action::Networks => {
+            let Some(conn) = app.conn() else {
+                app.set_error(String::from(NOT_CONNECTED));
+                return Ok(());
+            };
+
+            match list_networks(conn) {
+                Ok(networks) => app.mode = AppMode::Networks(NetworkView::new(networks)),
+                Err(e) => app.set_error(format!("Failed to list networks: {}", e)),
+            }
+        }
+        // Save what the screen of the selected vm shows
+        Action::Screenshot => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+
+            if current_item.state == DomainState::Running {
+                let name = current_item.name.clone();
+                app.save_screenshot(&name);
+            }
+        }
+        // Open the graphical console of the selected vm in a viewer
+        Action::Viewer => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+
+            if current_item.state == DomainState::Running {
+                let name = current_item.name.clone();
+                app.open_viewer(&name);
+            }
+        }
+        // Attach to the serial console of the selected vm
+        Action::Console => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+
+            if current_item.state == DomainState::Running {
+                let name = current_item.name.clone();
+                if !app.skip_in_dry_run(format!("Attached to the console of {}", name)) {
+                    app.open_console(&name);
+                }
+            }
+        }
+        // View the domain XML of the selected vm
+        Action::ViewXml => {
+            let Some(name) = app.selected_item().map(|item| item.name.clone()) else {
+                return Ok(());
+            };
+            let Some(conn) = app.conn() else {
+                app.set_error(String::from(NOT_CONNECTED));
+                return Ok(());
+            };
+
+            match get_xml(conn, &name) {
+                Ok(xml) => app.mode = AppMode::Text(TextView::new(format!("{} XML", name), &xml)),
+                Err(e) => app.set_error(format!("Could not read the XML of {}: {}", name, e)),
+            }
+        }
+        // Edit the definition of the selected vm in $EDITOR
+        Action::EditXml => {
+            let Some(current_item) = app.selected_item() else {
+                return Ok(());
+            };
+            let vm_name = current_item.name.clone();
+            if current_item.state.is_active() {
+                app.set_error(format!("Shut off {} before editing its XML", vm_name));
+                return Ok(());
+            }
+            let Some(conn) = app.conn() else {
+                app.set_error(String::from(NOT_CONNECTED));
+                return Ok(());
+            };
+
+            match get_editable_xml(conn, &vm_name) {
+                Ok(xml) => app.pending_edit = Some(XmlEdit { vm_name, xml }),
+                Err(e) => app.set_error(format!("Failed to read the XML of {}: {}", vm_name, e)),
+            }
+        }
+        Action::Filter => {
+            app.mode = AppMode::Filter;
+        }
+        Action::Jump => {
+            app.start_jump();
+        }
+        Action::Columns => {
+            app.mode = AppMode::Columns(ListState::default().with_selected(Some(0)));
+        }
+        Action::NextTab => {
+            app.next_tab();
+        }
+        Action::PrevTab => {
+            app.prev_tab();
+        }
+        Action::SortColumn => {
+            app.cycle_sort_column();
+        }
+        Action::SortDirection => {
+            app.toggle_sort_direction();
+        }
+        Action::StateFilter => {
+            app.cycle_state_filter();
+        }
+        Action::Group => {
+            app.toggle_grouping();
+        }
+        Action::Collapse => {
+            app.toggle_group();
+        }
+        Action::Theme => {
+            app.next_color();
+        }
+        Action::Units => {
+            app.cycle_units();
+        }
+        Action::Density => {
+            app.toggle_density();
+        }
+        Action::PerVcpu => {
+            app.toggle_per_vcpu();
+        }
+        // Freeze / unfreeze the periodic refresh, e.g. on slow remote connections
+        Action::Freeze => {
+            app.auto_refresh = !app.auto_refresh;
+        }
+        Action::ExportCsv => {
+            app.export(ExportFormat::Csv);
+        }
+        Action::ExportJson => {
+            app.export(ExportFormat::Json);
+        }
+        // Tail the recent log, newest entry at the bottom
+        Action::Help => {
+            let help = app.keymap.help();
+            app.mode = AppMode::Text(TextView::new(String::from("Keys"), &help));
+        }
+        Action::Log => {
+            let text = app.log.recent().collect::<Vec<_>>().join("\n");
+            let mut view = TextView::new(String::from("Log"), &text);
+            view.scroll_to(view.lines.len());
+            app.mode = AppMode::Text(view);
+        }
+        // Refresh stats right away
+        Action::Refresh => {
+            app.refresh();
+        }
+    }
+    Ok(())
+}
+
+/// Handles the key events while a confirmation dialog is open.
+fn handle_confirm_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    match key_event.code {
+        KeyCode::Char('y') | KeyCode::Char('Y') => match mem::take(&mut app.mode) {
+            AppMode::Confirm { action, vm_name } => run_vm_action(app, action, &vm_name),
+            AppMode::ConfirmBatch(batch) => run_batch(app, batch),
+            _ => {}
+        },
+        KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+            app.mode = AppMode::Normal;
+        }
         KeyCode::Char('s') => {
-            let current_item = &app.table_data[app.table_state.selected().unwrap()];
-            let name = &current_item.name;
+            if let AppMode::Confirm {
+                action: VmAction::Undefine { remove_storage },
+                ..
+            } = &mut app.mode
+            {
+                *remove_storage = !*remove_storage;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
 
-            snapshot(&app.conn, name);
+/// Handles the key events while the filter is being typed.
+fn handle_filter_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    match key_event.code {
+        KeyCode::Enter => {
+            app.mode = AppMode::Normal;
+        }
+        KeyCode::Esc => {
+            app.filter.clear();
+            app.mode = AppMode::Normal;
+        }
+        KeyCode::Backspace => {
+            app.filter.pop();
+        }
+        KeyCode::Char(c) => {
+            app.filter.push(c);
+        }
+        _ => return Ok(()),
+    }
+    app.refresh_rows();
+    Ok(())
+}
+
+fn open_snapshot_form(app: &mut App, disk_only: bool) {
+    let Some(current_item) = app.selected_item() else {
+        return;
+    };
+
+    let fields = vec![
+        FormField::new("Name", &format!("{}-<timestamp>", current_item.name)),
+        FormField::new("Description", "vmgr snapshot"),
+    ];
+    let mut form = Form::new(
+        FormKind::Snapshot { disk_only },
+        current_item.name.clone(),
+        fields,
+    );
+    if !disk_only {
+        form = form.with_note("A running vm is paused while its memory is saved.");
+    }
+    app.mode = AppMode::Form(form);
+}
+
+/// Handles the key events while a form dialog is open.
+fn handle_form_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    let AppMode::Form(form) = &mut app.mode else {
+        return Ok(());
+    };
+
+    match key_event.code {
+        KeyCode::Esc => {
+            app.mode = AppMode::Normal;
+        }
+        KeyCode::Tab | KeyCode::Down => {
+            form.focus = (form.focus + 1) % form.fields.len();
+        }
+        KeyCode::BackTab | KeyCode::Up => {
+            form.focus = (form.focus + form.fields.len() - 1) % form.fields.len();
+        }
+        KeyCode::Backspace => {
+            form.fields[form.focus].value.pop();
+        }
+        KeyCode::Char(c) => {
+            form.fields[form.focus].value.push(c);
+        }
+        KeyCode::Enter => {
+            if let AppMode::Form(form) = mem::take(&mut app.mode) {
+                if let Err(e) = submit_form(app, &form) {
+                    app.mode = AppMode::Form(Form {
+                        error: Some(e),
+                        ..form
+                    });
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Validates a submitted form and runs its action, or explains what is wrong.
+fn submit_form(app: &mut App, form: &Form) -> Result<(), String> {
+    match form.kind {
+        FormKind::Snapshot { disk_only } => submit_snapshot(app, form, disk_only),
+        FormKind::NewVm => submit_new_vm(app, form),
+        FormKind::Clone => submit_clone(app, form),
+        FormKind::Memory => submit_memory(app, form),
+        FormKind::Vcpus => submit_vcpus(app, form),
+        FormKind::IoLimits => submit_io_limits(app, form),
+        FormKind::PinVcpu => submit_pin_vcpu(app, form),
+        FormKind::Migrate => {
+            let uri = form.value(0);
+            if uri.is_empty() {
+                return Err(String::from("Destination URI is required"));
+            }
+            if app.session().host.as_ref().is_some_and(|h| h.uri == uri) {
+                return Err(String::from("Destination is the current hypervisor"));
+            }
+            app.mode = AppMode::Confirm {
+                action: VmAction::Migrate(uri.to_string()),
+                vm_name: form.vm_name.clone(),
+            };
+            Ok(())
+        }
+    }
+}
+
+fn submit_clone(app: &mut App, form: &Form) -> Result<(), String> {
+    let name = match form.value(0) {
+        "" => format!("{}-clone", form.vm_name),
+        name => name.to_string(),
+    };
+    if !is_xml_safe(&name) {
+        return Err(String::from("Name can't contain < > & \" or '"));
+    }
+    if app.session().metrics.iter().any(|m| m.name == name) {
+        return Err(format!("A vm named {} already exists", name));
+    }
+    if app.skip_in_dry_run(format!("Cloned {} to {}", form.vm_name, name)) {
+        return Ok(());
+    }
+    let conn = app.conn().ok_or(NOT_CONNECTED)?;
+
+    match clone_vm(conn, &form.vm_name, &name) {
+        Ok(()) => {
+            app.report(format!("Cloned {} to {}", form.vm_name, name));
+            app.refresh();
+        }
+        Err(e) => app.set_error(format!("Clone failed: {}", e)),
+    }
+    Ok(())
+}
+
+fn submit_memory(app: &mut App, form: &Form) -> Result<(), String> {
+    if form.value(0).is_empty() {
+        return Err(String::from("Memory is required"));
+    }
+    let requested =
+        parse_positive(form.value(0), 0).ok_or("Memory must be a positive number of MiB")?;
+    if requested < MIN_MEMORY {
+        return Err(format!("Memory must be at least {} MiB", MIN_MEMORY));
+    }
+    let max = app
+        .session()
+        .metrics
+        .iter()
+        .find(|m| m.name == form.vm_name)
+        .map_or(0, |m| m.mem_max / 1024);
+    // Libvirt would refuse anything above the maximum outright.
+    let memory = if max > 0 {
+        requested.min(max)
+    } else {
+        requested
+    };
+    if app.skip_in_dry_run(format!("Set memory of {} to {} MiB", form.vm_name, memory)) {
+        return Ok(());
+    }
+    let conn = app.conn().ok_or(NOT_CONNECTED)?;
+
+    match set_memory(conn, &form.vm_name, memory * 1024) {
+        Ok(()) if memory < requested => app.report(format!(
+            "Set memory of {} to its maximum of {} MiB",
+            form.vm_name, memory
+        )),
+        Ok(()) => app.report(format!("Set memory of {} to {} MiB", form.vm_name, memory)),
+        Err(e) => app.set_error(format!("Setting memory failed: {}", e)),
+    }
+    Ok(())
+}
+
+fn submit_vcpus(app: &mut App, form: &Form) -> Result<(), String> {
+    if form.value(0).is_empty() {
+        return Err(String::from("vCPUs are required"));
+    }
+    let count = parse_positive(form.value(0), 0).ok_or("vCPUs must be a positive number")?;
+    let max = app
+        .session()
+        .metrics
+        .iter()
+        .find(|m| m.name == form.vm_name)
+        .map_or(0, |m| m.vcpus_max);
+    if max > 0 && count > max {
+        return Err(format!("{} can have at most {} vCPUs", form.vm_name, max));
+    }
+    if app.skip_in_dry_run(format!("Set vCPUs of {} to {}", form.vm_name, count)) {
+        return Ok(());
+    }
+    let conn = app.conn().ok_or(NOT_CONNECTED)?;
+
+    match set_vcpus(conn, &form.vm_name, count as u32) {
+        Ok(()) => app.report(format!("Set vCPUs of {} to {}", form.vm_name, count)),
+        Err(e) => app.set_error(format!("Setting vCPUs failed: {}", e)),
+    }
+    Ok(())
+}
+
+fn submit_io_limits(app: &mut App, form: &Form) -> Result<(), String> {
+    // An empty field keeps the current limit, shown as the placeholder.
+    let limit = |i: usize| {
+        let value = match form.value(i) {
+            "" => form.fields[i].placeholder.as_str(),
+            value => value,
+        };
+        value.parse::<u64>().ok()
+    };
+    let disk = limit(0).ok_or("The disk limit must be a number of MiB/s")?;
+    let net = limit(1).ok_or("The network limit must be a number of KiB/s")?;
+    if app.skip_in_dry_run(format!("Limited the bandwidth of {}", form.vm_name)) {
+        return Ok(());
+    }
+    let conn = app.conn().ok_or(NOT_CONNECTED)?;
+
+    match set_io_limits(conn, &form.vm_name, disk.saturating_mul(1 << 20), net) {
+        Ok(()) => {
+            app.report(format!("Limited the bandwidth of {}", form.vm_name));
+            app.refresh();
+        }
+        Err(e) => app.set_error(format!("Limiting bandwidth failed: {}", e)),
+    }
+    Ok(())
+}
+
+fn submit_pin_vcpu(app: &mut App, form: &Form) -> Result<(), String> {
+    let vcpus = app
+        .session()
+        .metrics
+        .iter()
+        .find(|m| m.name == form.vm_name)
+        .map_or(0, |m| m.vcpus);
+    let vcpu: u64 = match form.value(0) {
+        "" => 0,
+        vcpu => vcpu.parse().map_err(|_| "vCPU must be a number")?,
+    };
+    if vcpu >= vcpus {
+        return Err(format!(
+            "{} has vCPUs 0-{} active",
+            form.vm_name,
+            vcpus.saturating_sub(1)
+        ));
+    }
+    if form.value(1).is_empty() {
+        return Err(String::from("Host CPUs are required"));
+    }
+    let Some(host_cpus) = app.session().host.as_ref().map(|h| h.cpus) else {
+        return Err(String::from("Host CPU count not known yet"));
+    };
+    let cpus = parse_cpuset(form.value(1), host_cpus)?;
+    let done = format!(
+        "Pinned vCPU {} of {} to CPUs {}",
+        vcpu,
+        form.vm_name,
+        format_cpuset(&cpus)
+    );
+    if app.skip_in_dry_run(done.clone()) {
+        return Ok(());
+    }
+    let conn = app.conn().ok_or(NOT_CONNECTED)?;
+
+    match pin_vcpu(conn, &form.vm_name, vcpu as u32, &cpus, host_cpus) {
+        Ok(()) => {
+            app.report(done);
+            app.refresh();
+        }
+        Err(e) => app.set_error(format!("Pinning failed: {}", e)),
+    }
+    Ok(())
+}
+
+fn submit_snapshot(app: &mut App, form: &Form, disk_only: bool) -> Result<(), String> {
+    let name = form.value(0);
+    let description = form.value(1);
+    if !is_xml_safe(name) || !is_xml_safe(description) {
+        return Err(String::from(
+            "Name and description can't contain < > & \" or '",
+        ));
+    }
+
+    if app.skip_in_dry_run(format!("Created snapshot of {}", form.vm_name)) {
+        return Ok(());
+    }
+    let conn = app.conn().ok_or(NOT_CONNECTED)?;
+    match snapshot(
+        conn,
+        &form.vm_name,
+        (!name.is_empty()).then_some(name),
+        (!description.is_empty()).then_some(description),
+        disk_only,
+    ) {
+        Ok(()) => app.report(format!("Created snapshot of {}", form.vm_name)),
+        Err(e) => app.set_error(format!("Snapshot failed: {}", e)),
+    }
+    Ok(())
+}
+
+fn submit_new_vm(app: &mut App, form: &Form) -> Result<(), String> {
+    let name = form.value(0);
+    if name.is_empty() {
+        return Err(String::from("Name is required"));
+    }
+    if !is_xml_safe(name) || !is_xml_safe(form.value(3)) {
+        return Err(String::from(
+            "Name and disk image can't contain < > & \" or '",
+        ));
+    }
+    if app.session().metrics.iter().any(|m| m.name == name) {
+        return Err(format!("A vm named {} already exists", name));
+    }
+    let vcpus = parse_positive(form.value(1), 1).ok_or("vCPUs must be a positive number")?;
+    let memory =
+        parse_positive(form.value(2), 1024).ok_or("Memory must be a positive number of MiB")?;
+    let disk = Some(form.value(3)).filter(|d| !d.is_empty());
+
+    if app.skip_in_dry_run(format!("Defined {}", name)) {
+        return Ok(());
+    }
+    let conn = app.conn().ok_or(NOT_CONNECTED)?;
+    match define_vm(conn, name, vcpus as u32, memory, disk) {
+        Ok(()) => {
+            app.report(format!("Defined {}", name));
+            app.refresh();
+        }
+        Err(e) => app.set_error(format!("Define failed: {}", e)),
+    }
+    Ok(())
+}
+
+/// Parses a number greater than zero, or returns `default` for an empty field.
+fn parse_positive(s: &str, default: u64) -> Option<u64> {
+    if s.is_empty() {
+        return Some(default);
+    }
+    s.parse().ok().filter(|&n| n > 0 && n <= u32::MAX.into())
+}
+
+/// Whether `s` can be put into an XML element without escaping.
+fn is_xml_safe(s: &str) -> bool {
+    !s.contains(['<', '>', '&', '"', '\''])
+}
+
+/// Handles mouse events: a click selects the row under the pointer and the
+/// wheel moves the selection, or scrolls whichever full screen view is open.
+pub fn handle_mouse_events(mouse_event: MouseEvent, app: &mut App) {
+    let delta = match mouse_event.kind {
+        MouseEventKind::ScrollUp => -1,
+        MouseEventKind::ScrollDown => 1,
+        MouseEventKind::Down(MouseButton::Left) => {
+            if app.mode == AppMode::Normal {
+                app.select_at(mouse_event.column, mouse_event.row);
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    match &mut app.mode {
+        AppMode::Normal => app.scroll(delta),
+        AppMode::Text(view) if delta < 0 => view.scroll_up(1),
+        AppMode::Text(view) => view.scroll_down(1),
+        AppMode::Storage(view) => view.scroll(delta),
+        AppMode::Networks(view) => view.scroll(delta),
+        _ => {}
+    }
+}
+
+/// Handles the key events while the snapshot browser is open.
+fn handle_snapshot_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    let AppMode::Snapshots {
+        vm_name,
+        snapshots,
+        list_state,
+    } = &mut app.mode
+    else {
+        return Ok(());
+    };
+
+    match key_event.code {
+        KeyCode::Esc | KeyCode::Char('q') => {
+            app.mode = AppMode::Normal;
+        }
+        KeyCode::Up | KeyCode::Char('k') => {
+            let i = list_state.selected().unwrap_or(0);
+            list_state.select(Some(i.saturating_sub(1)));
+        }
+        KeyCode::Down | KeyCode::Char('j') => {
+            let i = list_state.selected().map_or(0, |i| i + 1);
+            list_state.select(Some(i.min(snapshots.len().saturating_sub(1))));
+        }
+        // Revert to the highlighted snapshot, after confirming
+        KeyCode::Enter if app.read_only => {
+            app.set_error(String::from("The connection is read-only"));
+        }
+        KeyCode::Enter => {
+            if let Some(snapshot) = list_state.selected().and_then(|i| snapshots.get(i)) {
+                app.mode = AppMode::Confirm {
+                    action: VmAction::RevertSnapshot(snapshot.name.clone()),
+                    vm_name: vm_name.clone(),
+                };
+            }
         }
-        // Other handlers you could add here.
         _ => {}
     }
     Ok(())
 }
+
+/// Handles the key events while the storage view is open. `enter` drills
+/// into the highlighted pool, `esc` goes back up.
+fn handle_storage_key_events(key_event: KeyEvent, app: &mut App) {
+    let AppMode::Storage(view) = &mut app.mode else {
+        return;
+    };
+
+    match key_event.code {
+        KeyCode::Esc | KeyCode::Char('q') if view.volumes.is_some() => view.volumes = None,
+        KeyCode::Esc | KeyCode::Char('q') => app.mode = AppMode::Normal,
+        KeyCode::Up | KeyCode::Char('k') => view.scroll(-1),
+        KeyCode::Down | KeyCode::Char('j') => view.scroll(1),
+        KeyCode::Enter if view.volumes.is_none() => {
+            let Some(pool) = view.selected_pool().map(|pool| pool.name.clone()) else {
+                return;
+            };
+            let Some(conn) = app.sessions[app.current].conn.as_ref() else {
+                app.set_error(String::from(NOT_CONNECTED));
+                return;
+            };
+
+            match list_volumes(conn, &pool) {
+                Ok(volumes) => view.open_pool(pool, volumes),
+                Err(e) => app.set_error(format!("Failed to list volumes of {}: {}", pool, e)),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Handles the key events while the column picker is open. `space` and
+/// `enter` show or hide the highlighted column.
+fn handle_columns_key_events(key_event: KeyEvent, app: &mut App) {
+    let AppMode::Columns(list_state) = &mut app.mode else {
+        return;
+    };
+
+    match key_event.code {
+        KeyCode::Esc | KeyCode::Char('q') => app.mode = AppMode::Normal,
+        KeyCode::Up | KeyCode::Char('k') => {
+            let i = list_state.selected().unwrap_or(0);
+            list_state.select(Some(i.saturating_sub(1)));
+        }
+        KeyCode::Down | KeyCode::Char('j') => {
+            let i = list_state.selected().map_or(0, |i| i + 1);
+            list_state.select(Some(i.min(Column::ALL.len() - 1)));
+        }
+        KeyCode::Char(' ') | KeyCode::Enter => {
+            if let Some(&column) = list_state.selected().and_then(|i| Column::ALL.get(i)) {
+                app.toggle_column(column);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Forwards key presses to the guest until `ctrl-]` detaches, or any of
+/// `ctrl-]`, `esc` and `q` once the console has closed.
+fn handle_console_key_events(key_event: KeyEvent, app: &mut App) {
+    let Some(console) = &app.console else {
+        app.mode = AppMode::Normal;
+        return;
+    };
+
+    let closed = console.closed.is_some();
+    if is_detach(key_event) || closed && matches!(key_event.code, KeyCode::Esc | KeyCode::Char('q'))
+    {
+        app.close_console();
+    } else if !closed {
+        console.send_key(key_event);
+    }
+}
+
+/// Scrolls a full screen text view.
+fn handle_text_key_events(key_event: KeyEvent, view: &mut TextView) {
+    match key_event.code {
+        KeyCode::Up | KeyCode::Char('k') => view.scroll_up(1),
+        KeyCode::Down | KeyCode::Char('j') => view.scroll_down(1),
+        KeyCode::PageUp => view.scroll_up(view.page_size),
+        KeyCode::PageDown => view.scroll_down(view.page_size),
+        KeyCode::Home | KeyCode::Char('g') => view.scroll_to(0),
+        KeyCode::End | KeyCode::Char('G') => view.scroll_to(usize::MAX),
+        _ => {}
+    }
+}
+
+/// Runs a confirmed action, reporting the outcome in the status area.
+fn run_vm_action(app: &mut App, action: VmAction, name: &str) {
+    if app.skip_in_dry_run(action.done(name)) {
+        return;
+    }
+    match perform_vm_action(app, &action, name) {
+        Ok(()) => app.report(action.done(name)),
+        Err(message) => app.set_error(message),
+    }
+}
+
+/// Runs each action of a confirmed batch on its VM, and reports how every
+/// one of them went. The marks are cleared either way.
+fn run_batch(app: &mut App, batch: Vec<(VmAction, String)>) {
+    app.marked.clear();
+    let done = batch
+        .iter()
+        .map(|(action, name)| action.done(name))
+        .collect::<Vec<_>>()
+        .join("; ");
+    if app.skip_in_dry_run(done) {
+        return;
+    }
+
+    let mut failed = false;
+    let results = batch
+        .iter()
+        .map(
+            |(action, name)| match perform_vm_action(app, action, name) {
+                Ok(()) => action.done(name),
+                Err(message) => {
+                    failed = true;
+                    format!("{}: {}", name, message)
+                }
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("; ");
+    if failed {
+        app.set_error(results);
+    } else {
+        app.report(results);
+    }
+    app.refresh();
+}
+
+/// Runs `action` on the VM `name`, or says why it failed.
+fn perform_vm_action(app: &mut App, action: &VmAction, name: &str) -> Result<(), String> {
+    let conn = app.conn().ok_or(NOT_CONNECTED)?;
+    let result = match action {
+        VmAction::Start => start(conn, name),
+        VmAction::Shutdown => {
+            let result = stop(conn, name);
+            if result.is_ok() {
+                app.watch_shutdown(name);
+            }
+            result
+        }
+        VmAction::ForceOff => force_stop(conn, name),
+        VmAction::Reboot => reboot(conn, name),
+        VmAction::CtrlAltDel => send_ctrl_alt_del(conn, name),
+        VmAction::ManagedSave => managed_save(conn, name),
+        VmAction::ManagedSaveRemove => managed_save_remove(conn, name),
+        VmAction::Migrate(uri) => {
+            let result = migrate(conn, name, uri);
+            app.refresh();
+            result
+        }
+        VmAction::RevertSnapshot(snapshot) => revert_snapshot(conn, name, snapshot),
+        VmAction::Undefine { remove_storage } => {
+            let result = undefine(conn, name, *remove_storage);
+            // The deleted vm drops out with the next sample; the selection and
+            // scrollbar are clamped to the shorter list then.
+            app.refresh();
+            match result {
+                Ok(kept) if !kept.is_empty() => {
+                    return Err(format!(
+                        "Deleted {}, but could not delete {}",
+                        name,
+                        kept.join(", ")
+                    ));
+                }
+                result => result.map(drop),
+            }
+        }
+        VmAction::Snapshot { disk_only } => snapshot(conn, name, None, None, *disk_only),
+    };
+    result.map_err(|e| format!("{} failed: {}", action.verb(), e))
+}
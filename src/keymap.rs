@@ -0,0 +1,527 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Something a key can be bound to in the VM list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    Help,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+    NewVm,
+    StartStop,
+    Clone,
+    Migrate,
+    Delete,
+    ForceOff,
+    Reboot,
+    CtrlAltDel,
+    PauseResume,
+    SetMemory,
+    SetVcpus,
+    IoLimits,
+    PinVcpu,
+    Save,
+    DiscardSave,
+    Autostart,
+    Snapshot,
+    FullSnapshot,
+    Snapshots,
+    Screenshot,
+    Viewer,
+    Console,
+    Storage,
+    Networks,
+    ViewXml,
+    EditXml,
+    Filter,
+    Jump,
+    StateFilter,
+    Columns,
+    SortColumn,
+    SortDirection,
+    Group,
+    Collapse,
+    Theme,
+    Units,
+    Density,
+    PerVcpu,
+    Freeze,
+    Refresh,
+    ExportCsv,
+    ExportJson,
+    Log,
+    Mark,
+    Inactive,
+    Focus,
+    NextTab,
+    PrevTab,
+}
+
+impl Action {
+    /// Every action, in the order they are listed in the help.
+    pub const ALL: [Action; 56] = [
+        Action::Quit,
+        Action::Help,
+        Action::Up,
+        Action::Down,
+        Action::PageUp,
+        Action::PageDown,
+        Action::First,
+        Action::Last,
+        Action::NewVm,
+        Action::StartStop,
+        Action::Clone,
+        Action::Migrate,
+        Action::Delete,
+        Action::ForceOff,
+        Action::Reboot,
+        Action::CtrlAltDel,
+        Action::PauseResume,
+        Action::SetMemory,
+        Action::SetVcpus,
+        Action::IoLimits,
+        Action::PinVcpu,
+        Action::Save,
+        Action::DiscardSave,
+        Action::Autostart,
+        Action::Snapshot,
+        Action::FullSnapshot,
+        Action::Snapshots,
+        Action::Screenshot,
+        Action::Viewer,
+        Action::Console,
+        Action::Storage,
+        Action::Networks,
+        Action::ViewXml,
+        Action::EditXml,
+        Action::Filter,
+        Action::Jump,
+        Action::StateFilter,
+        Action::Columns,
+        Action::SortColumn,
+        Action::SortDirection,
+        Action::Group,
+        Action::Collapse,
+        Action::Theme,
+        Action::Units,
+        Action::Density,
+        Action::PerVcpu,
+        Action::Freeze,
+        Action::Refresh,
+        Action::ExportCsv,
+        Action::ExportJson,
+        Action::Log,
+        Action::Mark,
+        Action::Inactive,
+        Action::Focus,
+        Action::NextTab,
+        Action::PrevTab,
+    ];
+
+    /// Actions whose keys the footer lists; the help has the rest.
+    pub const FOOTER: [Action; 4] = [
+        Action::Help,
+        Action::Quit,
+        Action::StartStop,
+        Action::Filter,
+    ];
+
+    /// Name of the action in the `[keys]` table of the config file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Quit => "quit",
+            Self::Help => "help",
+            Self::Up => "up",
+            Self::Down => "down",
+            Self::PageUp => "page_up",
+            Self::PageDown => "page_down",
+            Self::First => "first",
+            Self::Last => "last",
+            Self::NewVm => "new_vm",
+            Self::StartStop => "start_stop",
+            Self::Clone => "clone",
+            Self::Migrate => "migrate",
+            Self::Delete => "delete",
+            Self::ForceOff => "force_off",
+            Self::Reboot => "reboot",
+            Self::CtrlAltDel => "ctrl_alt_del",
+            Self::PauseResume => "pause_resume",
+            Self::SetMemory => "set_memory",
+            Self::SetVcpus => "set_vcpus",
+            Self::IoLimits => "io_limits",
+            Self::PinVcpu => "pin_vcpu",
+            Self::Save => "save",
+            Self::DiscardSave => "discard_save",
+            Self::Autostart => "autostart",
+            Self::Snapshot => "snapshot",
+            Self::FullSnapshot => "full_snapshot",
+            Self::Snapshots => "snapshots",
+            Self::Screenshot => "screenshot",
+            Self::Viewer => "viewer",
+            Self::Console => "console",
+            Self::Storage => "storage",
+            Self::Networks => "networks",
+            Self::ViewXml => "view_xml",
+            Self::EditXml => "edit_xml",
+            Self::Filter => "filter",
+            Self::Jump => "jump",
+            Self::StateFilter => "state_filter",
+            Self::Columns => "columns",
+            Self::SortColumn => "sort_column",
+            Self::SortDirection => "sort_direction",
+            Self::Group => "group",
+            Self::Collapse => "collapse",
+            Self::Theme => "theme",
+            Self::Units => "units",
+            Self::Density => "density",
+            Self::PerVcpu => "per_vcpu",
+            Self::Freeze => "freeze",
+            Self::Refresh => "refresh",
+            Self::ExportCsv => "export_csv",
+            Self::ExportJson => "export_json",
+            Self::Log => "log",
+            Self::Mark => "mark",
+            Self::Inactive => "inactive",
+            Self::Focus => "focus",
+            Self::NextTab => "next_tab",
+            Self::PrevTab => "prev_tab",
+        }
+    }
+
+    /// What the action does, as shown in the help and footer.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Quit => "quit",
+            Self::Help => "help",
+            Self::Up => "move up",
+            Self::Down => "move down",
+            Self::PageUp => "page up",
+            Self::PageDown => "page down",
+            Self::First => "first",
+            Self::Last => "last",
+            Self::NewVm => "new vm",
+            Self::StartStop => "start / shutdown vm",
+            Self::Clone => "clone vm",
+            Self::Migrate => "migrate vm",
+            Self::Delete => "delete vm",
+            Self::ForceOff => "force off vm",
+            Self::Reboot => "reboot vm",
+            Self::CtrlAltDel => "send ctrl+alt+del",
+            Self::PauseResume => "pause / resume vm",
+            Self::SetMemory => "set memory",
+            Self::SetVcpus => "set vcpus",
+            Self::IoLimits => "limit bandwidth",
+            Self::PinVcpu => "pin vcpu",
+            Self::Save => "save vm",
+            Self::DiscardSave => "discard save",
+            Self::Autostart => "toggle autostart",
+            Self::Snapshot => "disk snapshot",
+            Self::FullSnapshot => "full snapshot",
+            Self::Snapshots => "snapshots",
+            Self::Screenshot => "screenshot",
+            Self::Viewer => "graphical console",
+            Self::Console => "serial console",
+            Self::Storage => "storage pools",
+            Self::Networks => "networks",
+            Self::ViewXml => "view xml",
+            Self::EditXml => "edit xml",
+            Self::Filter => "filter",
+            Self::Jump => "jump to name",
+            Self::StateFilter => "filter by state",
+            Self::Columns => "columns",
+            Self::SortColumn => "sort column",
+            Self::SortDirection => "sort direction",
+            Self::Group => "group by state",
+            Self::Collapse => "collapse group",
+            Self::Theme => "theme",
+            Self::Units => "units",
+            Self::Density => "row density",
+            Self::PerVcpu => "cpu per vcpu",
+            Self::Freeze => "freeze refresh",
+            Self::Refresh => "refresh",
+            Self::ExportCsv => "export csv",
+            Self::ExportJson => "export json",
+            Self::Log => "log",
+            Self::Mark => "mark for a batch",
+            Self::Inactive => "show shut off vms",
+            Self::Focus => "switch pane",
+            Self::NextTab => "next host",
+            Self::PrevTab => "previous host",
+        }
+    }
+
+    /// Does the action need a writable connection? Those are refused up
+    /// front on a read-only one rather than failing deep in libvirt: the
+    /// ones changing vms, and the console and screenshots, which libvirt
+    /// doesn't offer read-only either.
+    pub fn needs_write(&self) -> bool {
+        matches!(
+            self,
+            Self::NewVm
+                | Self::StartStop
+                | Self::Clone
+                | Self::Migrate
+                | Self::Delete
+                | Self::ForceOff
+                | Self::Reboot
+                | Self::CtrlAltDel
+                | Self::Console
+                | Self::Screenshot
+                | Self::EditXml
+                | Self::PauseResume
+                | Self::SetMemory
+                | Self::SetVcpus
+                | Self::IoLimits
+                | Self::PinVcpu
+                | Self::Save
+                | Self::DiscardSave
+                | Self::Autostart
+                | Self::Snapshot
+                | Self::FullSnapshot
+        )
+    }
+
+    /// Keys bound to the action unless the config file says otherwise.
+    fn default_keys(&self) -> &'static str {
+        match self {
+            Self::Quit => "q esc ctrl-c",
+            Self::Help => "?",
+            Self::Up => "up k",
+            Self::Down => "down j",
+            Self::PageUp => "pgup",
+            Self::PageDown => "pgdn",
+            Self::First => "home g",
+            Self::Last => "end G",
+            Self::NewVm => "n",
+            Self::StartStop => "x",
+            Self::Clone => "c",
+            Self::Migrate => "M",
+            Self::Delete => "D",
+            Self::ForceOff => "X",
+            Self::Reboot => "r",
+            Self::CtrlAltDel => "K",
+            Self::PauseResume => "p",
+            Self::SetMemory => "b",
+            Self::SetVcpus => "C",
+            Self::IoLimits => "B",
+            Self::PinVcpu => "A",
+            Self::Save => "w",
+            Self::DiscardSave => "W",
+            Self::Autostart => "a",
+            Self::Snapshot => "s",
+            Self::FullSnapshot => "m",
+            Self::Snapshots => "S",
+            Self::Screenshot => "P",
+            Self::Viewer => "d",
+            Self::Console => "T",
+            Self::Storage => "V",
+            Self::Networks => "N",
+            Self::ViewXml => "v",
+            Self::EditXml => "i",
+            Self::Filter => "/",
+            Self::Jump => "'",
+            Self::StateFilter => "F",
+            Self::Columns => "H",
+            Self::SortColumn => "o",
+            Self::SortDirection => "O",
+            Self::Group => "Z",
+            Self::Collapse => "z",
+            Self::Theme => "t",
+            Self::Units => "u",
+            Self::Density => "L",
+            Self::PerVcpu => "%",
+            Self::Freeze => "f",
+            Self::Refresh => "R f5",
+            Self::ExportCsv => "e",
+            Self::ExportJson => "E",
+            Self::Log => "l",
+            Self::Mark => "space",
+            Self::Inactive => "I",
+            Self::Focus => "tab ctrl-w",
+            Self::NextTab => "]",
+            Self::PrevTab => "[",
+        }
+    }
+}
+
+/// Maps keys to the [`Action`]s they trigger in the VM list.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: HashMap<KeyEvent, Action>,
+    /// Names of the keys bound to each action, joined by `/`, in the order
+    /// of [`Action::ALL`]. Actions without keys are left out.
+    keys: Vec<(Action, String)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(&HashMap::new()).expect("default key bindings conflict")
+    }
+}
+
+impl Keymap {
+    /// Builds the default keymap with `overrides` applied. An override maps an
+    /// action name to space separated keys (e.g. `quit = "Q ctrl-c"`) and
+    /// replaces all default keys of that action.
+    ///
+    /// Fails on unknown action names or keys, and on keys bound to more than
+    /// one action.
+    pub fn new(overrides: &HashMap<String, String>) -> Result<Self, String> {
+        for name in overrides.keys() {
+            if !Action::ALL.iter().any(|a| a.name() == name) {
+                return Err(format!("unknown action {} in [keys]", name));
+            }
+        }
+
+        let mut bindings = HashMap::new();
+        let mut bound = vec![];
+        for action in Action::ALL {
+            let keys = overrides
+                .get(action.name())
+                .map_or(action.default_keys(), String::as_str);
+
+            let mut names = vec![];
+            for key in keys.split_whitespace() {
+                let event = parse_key(key)?;
+                if let Some(other) = bindings.insert(event, action).filter(|&a| a != action) {
+                    return Err(format!(
+                        "{} is bound to both {} and {}",
+                        key,
+                        other.name(),
+                        action.name()
+                    ));
+                }
+                names.push(key_name(&event));
+            }
+            if !names.is_empty() {
+                bound.push((action, names.join("/")));
+            }
+        }
+
+        Ok(Self {
+            bindings,
+            keys: bound,
+        })
+    }
+
+    /// Returns the action bound to `key`, if any.
+    pub fn action(&self, key: KeyEvent) -> Option<Action> {
+        // Terminals report shift-tab as a back tab, and uppercase letters and
+        // symbols as themselves, with shift held on top.
+        let modifiers = match key.code {
+            KeyCode::BackTab | KeyCode::Char(_) => key.modifiers - KeyModifiers::SHIFT,
+            _ => key.modifiers,
+        };
+        self.bindings
+            .get(&KeyEvent::new(key.code, modifiers))
+            .copied()
+    }
+
+    /// Text of the help, a line per action with its keys and what it does.
+    pub fn help(&self) -> String {
+        let width = self
+            .keys
+            .iter()
+            .map(|(_, keys)| keys.chars().count())
+            .max()
+            .unwrap_or(0);
+        self.keys
+            .iter()
+            .map(|(action, keys)| format!("{:<width$}  {}", keys, action.description()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Footer text listing the keys of the [`Action::FOOTER`] actions.
+    pub fn footer(&self) -> String {
+        self.keys
+            .iter()
+            .filter(|(action, _)| Action::FOOTER.contains(action))
+            .map(|(action, keys)| format!("({}) {}", keys, action.description()))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+}
+
+/// Parses a key such as `q`, `G`, `pgdn` or `ctrl-c`.
+fn parse_key(s: &str) -> Result<KeyEvent, String> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = s;
+    loop {
+        if let Some(r) = rest.strip_prefix("ctrl-") {
+            modifiers |= KeyModifiers::CONTROL;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("alt-") {
+            modifiers |= KeyModifiers::ALT;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+
+    let code = match rest {
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "pgup" | "pageup" => KeyCode::PageUp,
+        "pgdn" | "pagedown" => KeyCode::PageDown,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "esc" => KeyCode::Esc,
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backtab" | "shift-tab" => KeyCode::BackTab,
+        "backspace" => KeyCode::Backspace,
+        "del" | "delete" => KeyCode::Delete,
+        "ins" | "insert" => KeyCode::Insert,
+        "space" => KeyCode::Char(' '),
+        f if f.len() > 1 && f.starts_with('f') => match f[1..].parse() {
+            Ok(n) if (1..=12).contains(&n) => KeyCode::F(n),
+            _ => return Err(format!("unknown key {}", s)),
+        },
+        c if c.chars().count() == 1 => KeyCode::Char(c.chars().next().unwrap()),
+        _ => return Err(format!("unknown key {}", s)),
+    };
+    Ok(KeyEvent::new(code, modifiers))
+}
+
+/// Short name of a key for the help and footer.
+fn key_name(key: &KeyEvent) -> String {
+    let code = match key.code {
+        KeyCode::Up => String::from("↑"),
+        KeyCode::Down => String::from("↓"),
+        KeyCode::Left => String::from("←"),
+        KeyCode::Right => String::from("→"),
+        KeyCode::PageUp => String::from("pgup"),
+        KeyCode::PageDown => String::from("pgdn"),
+        KeyCode::Home => String::from("home"),
+        KeyCode::End => String::from("end"),
+        KeyCode::Esc => String::from("esc"),
+        KeyCode::Enter => String::from("enter"),
+        KeyCode::Tab => String::from("tab"),
+        KeyCode::BackTab => String::from("shift-tab"),
+        KeyCode::Backspace => String::from("backspace"),
+        KeyCode::Delete => String::from("del"),
+        KeyCode::Insert => String::from("ins"),
+        KeyCode::Char(' ') => String::from("space"),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("f{}", n),
+        _ => String::from("?"),
+    };
+
+    let mut name = String::new();
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        name.push_str("ctrl-");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        name.push_str("alt-");
+    }
+    name + &code
+}
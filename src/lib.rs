@@ -13,4 +13,40 @@ pub mod tui;
 /// Event handler.
 pub mod handler;
 
+/// Command line arguments.
+pub mod cli;
+
+/// Background stats collection.
+pub mod collector;
+
+/// Serial console.
+pub mod console;
+
+/// Config file.
+pub mod config;
+
+/// Metrics export.
+pub mod export;
+
+/// Prometheus exporter.
+pub mod exporter;
+
+/// Key bindings.
+pub mod keymap;
+
+/// Domain lifecycle events.
+pub mod lifecycle;
+
+/// Event log.
+pub mod log;
+
+/// Virtual networks.
+pub mod network;
+
+/// Storage pools and volumes.
+pub mod storage;
+
+/// Byte count formatting.
+pub mod units;
+
 pub mod vms;
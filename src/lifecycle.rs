@@ -0,0 +1,94 @@
+use std::ffi::{c_int, c_void};
+use std::mem;
+use std::ptr;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use virt::sys::{
+    virConnectDomainEventDeregisterAny, virConnectDomainEventRegisterAny, virConnectPtr,
+    virDomainPtr, virEventRegisterDefaultImpl, virEventRunDefaultImpl,
+    VIR_DOMAIN_EVENT_ID_LIFECYCLE,
+};
+
+use crate::collector::RECONNECT_INTERVAL;
+use crate::event::Event;
+use crate::vms::{connect_read_only, disconnect};
+
+/// Type of the lifecycle callback, which libvirt takes cast to the generic
+/// callback type.
+type LifecycleCallback = unsafe extern "C" fn(
+    conn: virConnectPtr,
+    dom: virDomainPtr,
+    event: c_int,
+    detail: c_int,
+    opaque: *mut c_void,
+) -> c_int;
+
+/// Type of the callback `virConnectDomainEventRegisterAny` takes.
+type GenericCallback =
+    unsafe extern "C" fn(conn: virConnectPtr, dom: virDomainPtr, opaque: *mut c_void);
+
+/// Starts libvirt's event loop on a background thread. Has to be called
+/// before any connection is opened, as only connections opened afterwards
+/// deliver events. Returns whether the loop could be set up.
+pub fn init() -> bool {
+    if unsafe { virEventRegisterDefaultImpl() } < 0 {
+        return false;
+    }
+    thread::spawn(|| while unsafe { virEventRunDefaultImpl() } >= 0 {});
+    true
+}
+
+/// Sends [`Event::Lifecycle`] to `events` whenever a VM on the hypervisor at
+/// `uri` starts, stops, pauses, resumes or is (un)defined, so the table
+/// doesn't have to wait for the next refresh. The watch is given up if the
+/// driver doesn't support events, leaving the periodic refresh to notice
+/// the changes; a lost connection is reopened.
+pub fn watch(uri: &str, events: Sender<Event>) {
+    let uri = uri.to_string();
+    thread::spawn(move || loop {
+        let Ok(mut conn) = connect_read_only(&uri) else {
+            thread::sleep(RECONNECT_INTERVAL);
+            continue;
+        };
+
+        // Stays put until the callback is deregistered, as libvirt holds a
+        // pointer to it.
+        let sender = Box::new(events.clone());
+        let callback: LifecycleCallback = on_lifecycle;
+        let id = unsafe {
+            virConnectDomainEventRegisterAny(
+                conn.as_ptr(),
+                ptr::null_mut(),
+                VIR_DOMAIN_EVENT_ID_LIFECYCLE as c_int,
+                Some(mem::transmute::<LifecycleCallback, GenericCallback>(
+                    callback,
+                )),
+                &*sender as *const Sender<Event> as *mut c_void,
+                None,
+            )
+        };
+        if id < 0 {
+            disconnect(&mut conn);
+            return;
+        }
+
+        while conn.is_alive().unwrap_or(false) {
+            thread::sleep(RECONNECT_INTERVAL);
+        }
+        unsafe { virConnectDomainEventDeregisterAny(conn.as_ptr(), id) };
+        disconnect(&mut conn);
+    });
+}
+
+unsafe extern "C" fn on_lifecycle(
+    _conn: virConnectPtr,
+    _dom: virDomainPtr,
+    _event: c_int,
+    _detail: c_int,
+    opaque: *mut c_void,
+) -> c_int {
+    let events = &*(opaque as *const Sender<Event>);
+    let _ = events.send(Event::Lifecycle);
+    0
+}
@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use chrono::Local;
+
+/// Number of recent entries kept in memory for the log view.
+pub const RECENT_LEN: usize = 500;
+
+/// Size the log file grows to before it is rotated to `vmgr.log.1`.
+const MAX_LOG_SIZE: u64 = 1 << 20;
+
+/// How bad a logged event is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "INFO",
+            Self::Error => "ERROR",
+        }
+    }
+}
+
+/// Record of connection events, actions and failures, kept in memory for the
+/// log view and appended to `$XDG_STATE_HOME/vmgr/vmgr.log`. The file is
+/// written on a thread of its own so a slow disk never holds up the
+/// interface.
+#[derive(Debug, Default)]
+pub struct Log {
+    recent: VecDeque<String>,
+    writer: Option<Sender<String>>,
+}
+
+impl Log {
+    /// Starts the writer thread. Logging stays in memory only if there is no
+    /// state directory to put the file in.
+    pub fn open() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+
+        let (writer, lines) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            let mut file = None;
+            while let Ok(line) = lines.recv() {
+                if file.is_none() {
+                    file = open_file(&path);
+                }
+                let Some(f) = &mut file else {
+                    continue;
+                };
+                if writeln!(f, "{}", line).is_err() {
+                    file = None;
+                    continue;
+                }
+                if f.metadata().is_ok_and(|m| m.len() >= MAX_LOG_SIZE) {
+                    file = None;
+                    let _ = fs::rename(&path, path.with_extension("log.1"));
+                }
+            }
+        });
+        Self {
+            recent: VecDeque::new(),
+            writer: Some(writer),
+        }
+    }
+
+    /// Location of the log file, or `None` when neither `XDG_STATE_HOME` nor
+    /// `HOME` is set.
+    pub fn path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?)
+                .join(".local")
+                .join("state"),
+        };
+        Some(dir.join("vmgr").join("vmgr.log"))
+    }
+
+    /// Records `message`, stamped with the local time.
+    pub fn record(&mut self, level: Level, message: &str) {
+        let line = format!(
+            "{} {:<5} {}",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            level.as_str(),
+            message
+        );
+        if self.recent.len() == RECENT_LEN {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(line.clone());
+        if let Some(writer) = &self.writer {
+            let _ = writer.send(line);
+        }
+    }
+
+    /// The most recent entries, oldest first.
+    pub fn recent(&self) -> impl Iterator<Item = &str> {
+        self.recent.iter().map(String::as_str)
+    }
+}
+
+/// Opens the log file for appending, creating it and its directory.
+fn open_file(path: &Path) -> Option<File> {
+    fs::create_dir_all(path.parent()?).ok()?;
+    OpenOptions::new().create(true).append(true).open(path).ok()
+}
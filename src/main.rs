@@ -1,36 +1,103 @@
-use ratatui::backend::CrosstermBackend;
+use clap::Parser;
+use ratatui::backend::{Backend, CrosstermBackend};
 use ratatui::Terminal;
 use std::io;
-use vmgr::app::{App, AppResult};
+use std::process;
+use std::time::Duration;
+use vmgr::app::{App, AppResult, Session};
+use vmgr::cli::Args;
+use vmgr::config::Config;
 use vmgr::event::{Event, EventHandler};
-use vmgr::handler::handle_key_events;
+use vmgr::export::{dump_json, print_list};
+use vmgr::exporter::serve_metrics;
+use vmgr::handler::{handle_key_events, handle_mouse_events};
+use vmgr::lifecycle;
 use vmgr::tui::Tui;
 
 fn main() -> AppResult<()> {
-    // Create an application.
-    let mut app = App::new();
+    let args = Args::parse();
+
+    // Modes for scripts and scrapers, which never touch the terminal state.
+    if args.list || args.dump_json || args.metrics_port.is_some() {
+        let (config, warning) = Config::load_with_args(&args);
+        if let Some(warning) = warning {
+            eprintln!("{}", warning);
+        }
+        let interval = Duration::from_millis(config.refresh);
+        let result = match args.metrics_port {
+            Some(port) => serve_metrics(&config.uri, args.metrics_addr, port, interval),
+            None if args.list => print_list(&config.uri, interval, config.units),
+            None => dump_json(&config.uri, interval),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Libvirt only delivers VM state changes on connections opened after its
+    // event loop is set up.
+    let watch_lifecycle = lifecycle::init();
+
+    // Create an application. This happens before the terminal is put into raw
+    // mode so a config failure can be reported as a plain message.
+    let mut app = match App::new(&args) {
+        Ok(app) => app,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
 
     // Initialize the terminal user interface.
     let backend = CrosstermBackend::new(io::stderr());
     let terminal = Terminal::new(backend)?;
-    let events = EventHandler::new(1000);
+    let events = EventHandler::new(app.config.refresh);
+    app.events = Some(events.sender());
+    // The first sample is taken once the interface is up, which shows that
+    // it is connecting in the meantime.
+    app.refresh();
+    if watch_lifecycle {
+        for session in &app.sessions {
+            lifecycle::watch(&session.uri, events.sender());
+        }
+    }
     let mut tui = Tui::new(terminal, events);
     tui.init()?;
 
-    // Start the main loop.
+    // Start the main loop. The terminal is restored even if it fails.
+    let result = run(&mut tui, &mut app);
+
+    // Exit the user interface.
+    tui.exit()?;
+    result
+}
+
+fn run<B: Backend>(tui: &mut Tui<B>, app: &mut App) -> AppResult<()> {
     while app.running {
         // Render the user interface.
-        tui.draw(&mut app)?;
+        tui.draw(app)?;
+        // The connecting spinner turns faster than the refresh interval.
+        tui.events
+            .animate(app.sessions.iter().any(Session::is_connecting));
         // Handle events.
         match tui.events.next()? {
-            Event::Tick => app.tick(),
-            Event::Key(key_event) => handle_key_events(key_event, &mut app)?,
-            Event::Mouse(_) => {}
-            Event::Resize(_, _) => {}
+            Event::Tick => {
+                app.expire_status();
+                app.tick();
+            }
+            Event::Key(key_event) => handle_key_events(key_event, app)?,
+            Event::Console => app.poll_console(),
+            Event::Sample => app.apply_samples(),
+            Event::Lifecycle => app.domains_changed(),
+            Event::Mouse(mouse_event) => handle_mouse_events(mouse_event, app),
+            Event::Resize(_, _) | Event::Frame => {}
+        }
+        if let Some(edit) = app.pending_edit.take() {
+            let edited = tui.edit(&edit.vm_name, &edit.xml);
+            app.finish_edit(edit, edited);
         }
     }
-
-    // Exit the user interface.
-    tui.exit()?;
     Ok(())
 }
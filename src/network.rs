@@ -0,0 +1,69 @@
+use virt::connect::Connect;
+use virt::error::Error;
+use virt::sys::VIR_CONNECT_LIST_DOMAINS_ACTIVE;
+
+use crate::vms::{xml_attribute, xml_blocks};
+
+/// A virtual network, as listed in the network view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInfo {
+    pub name: String,
+    pub active: bool,
+    pub autostart: bool,
+    /// Host bridge the network's guests are plugged into, empty for networks
+    /// without one (e.g. `hostdev`).
+    pub bridge: String,
+    /// Running VMs with an interface on the network or its bridge.
+    pub vms: Vec<String>,
+}
+
+/// Lists the virtual networks of the hypervisor, sorted by name, along with
+/// the running VMs attached to each.
+pub fn list_networks(conn: &Connect) -> Result<Vec<NetworkInfo>, Error> {
+    let attachments = attachments(conn)?;
+    let mut networks = vec![];
+
+    for net in conn.list_all_networks(0)? {
+        let name = net.get_name()?;
+        let bridge = net.get_bridge_name().unwrap_or_default();
+        let vms = attachments
+            .iter()
+            .filter(|(_, source)| *source == name || !bridge.is_empty() && *source == bridge)
+            .map(|(vm, _)| vm.clone())
+            .collect();
+        networks.push(NetworkInfo {
+            active: net.is_active()?,
+            autostart: net.get_autostart().unwrap_or(false),
+            name,
+            bridge,
+            vms,
+        });
+    }
+
+    networks.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(networks)
+}
+
+/// Returns `(vm, source)` for each interface of the running VMs, where
+/// `source` is the network or bridge the interface is plugged into.
+fn attachments(conn: &Connect) -> Result<Vec<(String, String)>, Error> {
+    let mut attachments = vec![];
+
+    for dom in conn.list_all_domains(VIR_CONNECT_LIST_DOMAINS_ACTIVE)? {
+        let name = dom.get_name()?;
+        let xml = dom.get_xml_desc(0)?;
+        for iface in xml_blocks(&xml, "interface") {
+            let Some(source) = xml_blocks(iface, "source").into_iter().next() else {
+                continue;
+            };
+            let source =
+                xml_attribute(source, "network").or_else(|| xml_attribute(source, "bridge"));
+            if let Some(source) = source {
+                if !attachments.iter().any(|(vm, s)| *vm == name && s == source) {
+                    attachments.push((name.clone(), source.to_string()));
+                }
+            }
+        }
+    }
+    Ok(attachments)
+}
@@ -0,0 +1,96 @@
+use virt::connect::Connect;
+use virt::error::Error;
+use virt::storage_pool::StoragePool;
+
+/// A storage pool, as listed in the storage view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolInfo {
+    pub name: String,
+    /// Pool state (e.g. `running`, `inactive`).
+    pub state: &'static str,
+    pub autostart: bool,
+    /// Sizes in bytes, zero while the pool is inactive.
+    pub capacity: u64,
+    pub allocation: u64,
+    pub available: u64,
+}
+
+/// A volume in a storage pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeInfo {
+    pub name: String,
+    /// Volume type (e.g. `file`, `block`).
+    pub kind: &'static str,
+    pub path: String,
+    /// Logical size in bytes.
+    pub capacity: u64,
+    /// Bytes actually taken up on the host, below `capacity` for sparse
+    /// images.
+    pub allocation: u64,
+}
+
+/// Lists the storage pools of the hypervisor, sorted by name.
+pub fn list_pools(conn: &Connect) -> Result<Vec<PoolInfo>, Error> {
+    let mut pools = vec![];
+
+    for pool in conn.list_all_storage_pools(0)? {
+        let info = pool.get_info()?;
+        pools.push(PoolInfo {
+            name: pool.get_name()?,
+            state: pool_state(info.state),
+            autostart: pool.get_autostart().unwrap_or(false),
+            capacity: info.capacity,
+            allocation: info.allocation,
+            available: info.available,
+        });
+    }
+
+    pools.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(pools)
+}
+
+/// Lists the volumes of the named pool, sorted by name. Fails if the pool
+/// isn't running.
+pub fn list_volumes(conn: &Connect, pool: &str) -> Result<Vec<VolumeInfo>, Error> {
+    let pool = StoragePool::lookup_by_name(conn, pool)?;
+    let mut volumes = vec![];
+
+    for vol in pool.list_all_volumes(0)? {
+        let info = vol.get_info()?;
+        volumes.push(VolumeInfo {
+            name: vol.get_name()?,
+            kind: volume_kind(info.kind),
+            path: vol.get_path().unwrap_or_default(),
+            capacity: info.capacity,
+            allocation: info.allocation,
+        });
+    }
+
+    volumes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(volumes)
+}
+
+/// Name of a `virStoragePoolState`.
+fn pool_state(state: u32) -> &'static str {
+    match state {
+        0 => "inactive",
+        1 => "building",
+        2 => "running",
+        3 => "degraded",
+        4 => "inaccessible",
+        _ => "unknown",
+    }
+}
+
+/// Name of a `virStorageVolType`.
+fn volume_kind(kind: u32) -> &'static str {
+    match kind {
+        0 => "file",
+        1 => "block",
+        2 => "dir",
+        3 => "network",
+        4 => "netdir",
+        5 => "ploop",
+        _ => "unknown",
+    }
+}
@@ -1,12 +1,16 @@
 use crate::app::{App, AppResult};
 use crate::event::EventHandler;
 use crate::ui;
+use crossterm::cursor;
 use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
 use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
 use ratatui::backend::Backend;
 use ratatui::Terminal;
+use std::env;
+use std::fs;
 use std::io;
 use std::panic;
+use std::process::{self, Command};
 
 /// Representation of a terminal user interface.
 ///
@@ -30,22 +34,58 @@ impl<B: Backend> Tui<B> {
     ///
     /// It enables the raw mode and sets terminal properties.
     pub fn init(&mut self) -> AppResult<()> {
-        terminal::enable_raw_mode()?;
-        crossterm::execute!(io::stderr(), EnterAlternateScreen, EnableMouseCapture)?;
-
         // Define a custom panic hook to reset the terminal properties.
         // This way, you won't have your terminal messed up if an unexpected error happens.
+        // The reset runs before the message is printed so it lands on the normal
+        // screen, and a failed reset must not panic again inside the hook.
         let panic_hook = panic::take_hook();
         panic::set_hook(Box::new(move |panic| {
-            Self::reset().expect("failed to reset the terminal");
+            let _ = Self::reset();
             panic_hook(panic);
         }));
 
+        self.enter()
+    }
+
+    /// Puts the terminal into raw mode on the alternate screen.
+    fn enter(&mut self) -> AppResult<()> {
+        terminal::enable_raw_mode()?;
+        crossterm::execute!(io::stderr(), EnterAlternateScreen, EnableMouseCapture)?;
         self.terminal.hide_cursor()?;
         self.terminal.clear()?;
         Ok(())
     }
 
+    /// Hands the terminal to `$VISUAL` or `$EDITOR` (`vi` if neither is set)
+    /// to edit `text`, saved in a temporary file named after `name`, and
+    /// returns the edited text. The interface is restored afterwards, even if
+    /// the editor fails.
+    pub fn edit(&mut self, name: &str, text: &str) -> AppResult<String> {
+        let path = env::temp_dir().join(format!("vmgr-{}-{}.xml", process::id(), name));
+        fs::write(&path, text)?;
+
+        self.events.pause();
+        let edited = Self::reset().and_then(|()| {
+            let editor = env::var("VISUAL")
+                .or_else(|_| env::var("EDITOR"))
+                .unwrap_or_else(|_| String::from("vi"));
+            // The editor may come with arguments, e.g. `code --wait`.
+            let mut words = editor.split_whitespace();
+            let program = words.next().unwrap_or("vi");
+            let status = Command::new(program).args(words).arg(&path).status()?;
+            if !status.success() {
+                return Err(format!("{} exited with {}", editor, status).into());
+            }
+            Ok(fs::read_to_string(&path)?)
+        });
+        let restored = self.enter();
+        self.events.resume();
+
+        let _ = fs::remove_file(&path);
+        restored?;
+        edited
+    }
+
     /// [`Draw`] the terminal interface by [`rendering`] the widgets.
     ///
     /// [`Draw`]: ratatui::Terminal::draw
@@ -61,7 +101,12 @@ impl<B: Backend> Tui<B> {
     /// the terminal properties if unexpected errors occur.
     fn reset() -> AppResult<()> {
         terminal::disable_raw_mode()?;
-        crossterm::execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture)?;
+        crossterm::execute!(
+            io::stderr(),
+            LeaveAlternateScreen,
+            DisableMouseCapture,
+            cursor::Show
+        )?;
         Ok(())
     }
 
@@ -1,38 +1,114 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::DateTime;
 use ratatui::{
-    layout::Constraint,
+    layout::{Constraint, Flex},
     prelude::*,
     style::Style,
     widgets::{
-        Block, BorderType, Cell, HighlightSpacing, Paragraph, Row, Scrollbar, ScrollbarOrientation,
-        Table, Wrap,
+        Block, BorderType, Cell, Clear, HighlightSpacing, List, ListItem, ListState, Paragraph,
+        Row, Scrollbar, ScrollbarOrientation, Sparkline, Table, Wrap,
     },
     Frame,
 };
 
-use crate::app::App;
-
-const INFO_TEXT: &str =
-    "(q) quit | (↑) move up | (↓) move down | (x) start / stop vm | (s) snapshot vm";
+use crate::app::{
+    batch_question, App, AppMode, Column, Density, Focus, Form, NetworkView, StateFilter,
+    StorageView, TableColors, TableRow, TextView, HISTORY_LEN, STATUS_TIMEOUT,
+};
+use crate::console::Console;
+use crate::units::Units;
+use crate::vms::{format_cpuset, DomainState, SnapshotInfo};
 
 pub fn render(f: &mut Frame, app: &mut App) {
+    let [header_area, main_area] =
+        Layout::vertical([Constraint::Length(4), Constraint::Min(0)]).areas(f.size());
     let layout =
-        Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)]).split(f.size());
+        Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)]).split(main_area);
     let upper_layout = Layout::horizontal([
         Constraint::Min(1),
         Constraint::Percentage(70),
         Constraint::Min(1),
     ])
     .split(layout[0]);
-    let table_layout =
-        Layout::vertical([Constraint::Min(5), Constraint::Length(3)]).split(layout[1]);
+    let table_layout = Layout::vertical([
+        Constraint::Min(5),
+        Constraint::Length(1),
+        Constraint::Length(3),
+    ])
+    .split(layout[1]);
 
+    render_header(f, app, header_area);
     render_overview(f, app, upper_layout[1]);
     render_table(f, app, table_layout[0]);
     render_scrollbar(f, app, table_layout[0]);
-    render_footer(f, app, table_layout[1]);
+    render_status(f, app, table_layout[1]);
+    render_footer(f, app, table_layout[2]);
+
+    // Overlays are drawn last so they sit on top of the main layout.
+    let colors = &app.colors;
+    match &mut app.mode {
+        AppMode::Confirm { action, vm_name } => {
+            render_confirm(f, colors, &action.question(vm_name));
+        }
+        AppMode::ConfirmBatch(batch) => render_confirm(f, colors, &batch_question(batch)),
+        AppMode::ConfirmQuit => render_confirm(f, colors, "Quit vmgr?"),
+        AppMode::Snapshots {
+            vm_name,
+            snapshots,
+            list_state,
+        } => render_snapshots(f, colors, vm_name, snapshots, list_state),
+        AppMode::Text(view) => render_text_view(f, colors, view, f.size()),
+        AppMode::Console => {
+            if let Some(console) = &app.console {
+                render_console(f, colors, console, f.size());
+            }
+        }
+        AppMode::Storage(view) => render_storage(f, colors, app.units, view, f.size()),
+        AppMode::Networks(view) => render_networks(f, colors, view, f.size()),
+        AppMode::Form(form) => render_form(f, colors, form),
+        AppMode::Columns(list_state) => render_columns(f, colors, &app.columns, list_state),
+        AppMode::Normal | AppMode::Filter | AppMode::Jump => {}
+    }
 }
 
 fn render_table(f: &mut Frame, app: &mut App, area: Rect) {
+    let block = Block::bordered()
+        .border_type(BorderType::Thick)
+        .border_style(pane_border(&app.colors, app.focus == Focus::Table))
+        .bg(app.colors.buffer_bg);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let area = inner;
+
+    let session = &mut app.sessions[app.current];
+    if session.rows.is_empty() {
+        let states = match app.state_filter {
+            StateFilter::All => String::new(),
+            states => format!("{} ", states.name()),
+        };
+        let text = if session.is_connecting() {
+            format!("\nConnecting to {}…", session.uri)
+        } else if app.filter.is_empty() {
+            format!("\nNo {}VMs found", states)
+        } else {
+            format!("\nNo {}VMs match \"{}\"", states, app.filter)
+        };
+        let placeholder = Paragraph::new(text)
+            .style(Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg))
+            .centered();
+        f.render_widget(placeholder, area);
+        return;
+    }
+
+    // One line goes to the header, the rest is split into rows.
+    let density = app.density;
+    let row_height = density.row_height();
+    app.page_size = (usize::from(area.height.saturating_sub(1)) / row_height).max(1);
+    app.table_area = area;
+
     let header_style = Style::default()
         .fg(app.colors.header_fg)
         .bg(app.colors.header_bg);
@@ -41,67 +117,208 @@ fn render_table(f: &mut Frame, app: &mut App, area: Rect) {
         .add_modifier(Modifier::REVERSED)
         .fg(app.colors.selected_style_fg);
 
-    let header = ["id", "name", "cpu usage", "memory usage", "status"]
+    // A gutter marks the VMs picked for a batch action, while there are any.
+    let gutter = !app.marked.is_empty();
+    let header = gutter
+        .then(|| Cell::from(""))
         .into_iter()
-        .map(Cell::from)
+        .chain(app.columns.iter().map(|&column| {
+            let title = column_title(column, app.per_vcpu);
+            match app.sort_column {
+                Some(sort) if sort.title() == column.title() => {
+                    let arrow = if app.sort_descending { "▼" } else { "▲" };
+                    Cell::from(format!("{} {arrow}", title))
+                }
+                _ => Cell::from(title),
+            }
+        }))
         .collect::<Row>()
         .style(header_style)
         .height(1);
 
-    let rows = app.table_data.iter().enumerate().map(|(i, data)| {
+    let rows = session.rows.iter().enumerate().map(|(i, &row)| {
+        let index = match row {
+            TableRow::Vm(index) => index,
+            TableRow::Group {
+                group,
+                count,
+                collapsed,
+            } => {
+                let arrow = if collapsed { "▸" } else { "▾" };
+                let heading = density.pad(&format!("{} {} ({})", arrow, group.name(), count));
+                // The heading goes under the name, or the first column if
+                // that is hidden.
+                let at = app
+                    .columns
+                    .iter()
+                    .position(|&c| c == Column::Name)
+                    .unwrap_or(0);
+                return gutter
+                    .then(|| Cell::from(""))
+                    .into_iter()
+                    .chain((0..app.columns.len()).map(|i| {
+                        if i == at {
+                            Cell::from(Text::from(heading.clone()))
+                        } else {
+                            Cell::from("")
+                        }
+                    }))
+                    .collect::<Row>()
+                    .style(header_style)
+                    .height(row_height as u16);
+            }
+        };
+        let data = &session.table_data[index];
         let color = match i % 2 {
             0 => app.colors.normal_row_color,
             _ => app.colors.alt_row_color,
         };
 
-        let item = data.ref_array();
-        item.into_iter()
-            .map(|content| Cell::from(Text::from(format!("\n{content}\n"))))
+        let status_style = Style::new().fg(app.colors.state_fg(data.state));
+        let marked = app.marked.contains(&session.metrics[index].uuid);
+        gutter
+            .then(|| Cell::from(Text::from(density.pad(if marked { "●" } else { "" }))))
+            .into_iter()
+            .chain(app.columns.iter().map(|&column| {
+                let cell = Cell::from(Text::from(density.pad(data.cell(column))));
+                if column == Column::Status {
+                    cell.style(status_style)
+                } else {
+                    cell
+                }
+            }))
             .collect::<Row>()
             .style(Style::new().fg(app.colors.row_fg).bg(color))
-            .height(4)
+            .height(row_height as u16)
     });
     let bar = " █ ";
     let t = Table::new(
         rows,
-        [
-            Constraint::Length(app.max_item_lens.0 + 1),
-            Constraint::Min(app.max_item_lens.1 + 1),
-            Constraint::Min(app.max_item_lens.2 + 1),
-            Constraint::Min(app.max_item_lens.3 + 1),
-            Constraint::Min(app.max_item_lens.4 + 1),
-        ],
+        gutter
+            .then_some(Constraint::Length(1))
+            .into_iter()
+            .chain(app.columns.iter().map(|&column| {
+                let width = session.max_item_lens[column as usize] + 1;
+                if column == Column::Id {
+                    Constraint::Length(width)
+                } else {
+                    Constraint::Min(width)
+                }
+            })),
     )
     .header(header)
     .highlight_style(selected_style)
-    .highlight_symbol(Text::from(vec![
-        "".into(),
-        bar.into(),
-        bar.into(),
-        "".into(),
-    ]))
+    .highlight_symbol(match density {
+        Density::Comfortable => Text::from(vec!["".into(), bar.into(), bar.into(), "".into()]),
+        Density::Compact => Text::from(bar),
+    })
     .bg(app.colors.buffer_bg)
     .highlight_spacing(HighlightSpacing::Always);
 
-    f.render_stateful_widget(t, area, &mut app.table_state)
+    f.render_stateful_widget(t, area, &mut session.table_state)
+}
+
+/// Style of the border of a pane, which stands out while the pane has the
+/// focus.
+fn pane_border(colors: &TableColors, focused: bool) -> Style {
+    if focused {
+        Style::new().fg(colors.selected_style_fg)
+    } else {
+        Style::new().fg(colors.footer_border_color)
+    }
 }
 
 fn render_scrollbar(f: &mut Frame, app: &mut App, area: Rect) {
+    // Drawn over the right border of the table, below its header.
     f.render_stateful_widget(
         Scrollbar::default()
             .orientation(ScrollbarOrientation::VerticalRight)
             .begin_symbol(None)
             .end_symbol(None),
         area.inner(&Margin {
-            vertical: 1,
-            horizontal: 1,
+            vertical: 2,
+            horizontal: 0,
         }),
-        &mut app.scroll_state,
+        &mut app.sessions[app.current].scroll_state,
     );
 }
 
+/// Draws the last status message until it times out, or a banner while the
+/// connection is lost.
+fn render_status(f: &mut Frame, app: &App, area: Rect) {
+    let session = app.session();
+    if let Some(e) = &session.disconnected {
+        let banner = Paragraph::new(format!(
+            "Connection to {} lost, reconnecting… ({})",
+            session.uri, e
+        ))
+        .style(
+            Style::new()
+                .fg(app.colors.crashed_fg)
+                .bg(app.colors.buffer_bg)
+                .add_modifier(Modifier::BOLD),
+        )
+        .centered();
+        f.render_widget(banner, area);
+        return;
+    }
+
+    let Some((message, set_at)) = &app.status_message else {
+        return;
+    };
+    if set_at.elapsed() >= STATUS_TIMEOUT {
+        return;
+    }
+
+    let fg = if app.status_is_error {
+        app.colors.crashed_fg
+    } else {
+        app.colors.running_fg
+    };
+    let status = Paragraph::new(message.as_str())
+        .style(Style::new().fg(fg).bg(app.colors.buffer_bg))
+        .centered();
+    f.render_widget(status, area);
+}
+
 fn render_footer(f: &mut Frame, app: &App, area: Rect) {
-    let info_footer = Paragraph::new(Line::from(INFO_TEXT))
+    let text = if app.mode == AppMode::Filter {
+        format!("filter: {}█ | (enter) apply | (esc) clear", app.filter)
+    } else if app.mode == AppMode::Jump {
+        format!("jump to: {}█ | (esc) done", app.jump_prefix)
+    } else if !app.filter.is_empty() {
+        format!(
+            "filter: {} (esc to clear) | {}",
+            app.filter,
+            app.keymap.footer()
+        )
+    } else {
+        app.keymap.footer()
+    };
+    let text = match app.state_filter {
+        StateFilter::All => text,
+        states => format!("{} vms only | {}", states.name(), text),
+    };
+    let text = if app.auto_refresh {
+        text
+    } else {
+        format!("refresh frozen | {}", text)
+    };
+    let text = if app.read_only {
+        format!("read-only | {}", text)
+    } else {
+        text
+    };
+    let text = match app.marked.len() {
+        0 => text,
+        marked => format!("{} marked, space to unmark | {}", marked, text),
+    };
+    let text = if app.dry_run {
+        format!("dry run | {}", text)
+    } else {
+        text
+    };
+    let info_footer = Paragraph::new(Line::from(text))
         .style(Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg))
         .centered()
         .block(
@@ -112,40 +329,761 @@ fn render_footer(f: &mut Frame, app: &App, area: Rect) {
     f.render_widget(info_footer, area);
 }
 
-fn render_overview(f: &mut Frame, app: &App, area: Rect) {
-    let index = app.table_state.selected().unwrap();
-    let overview = Paragraph::new(vec![
-        Line::from(format!("Name: {}", app.table_data[index].name)),
-        Line::from(format!("Status: {}", app.table_data[index].status)),
-        Line::from(format!("CPU Usage: {}", app.table_data[index].cpu_usage)),
-        Line::from(format!("Mem Usage: {}", app.table_data[index].mem_usage)),
-        Line::from(format!("Network: {}", app.metrics[index].net_name)),
+fn render_header(f: &mut Frame, app: &App, area: Rect) {
+    let block = Block::bordered()
+        .title(tab_titles(app))
+        .border_type(BorderType::Thick)
+        .border_style(Style::new().fg(app.colors.footer_border_color));
+    let session = app.session();
+    if session.is_connecting() {
+        let header = Paragraph::new(format!("Connecting to {} {}", session.uri, spinner()))
+            .style(Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg))
+            .centered()
+            .block(block);
+        f.render_widget(header, area);
+        return;
+    }
+    let Some(host) = &session.host else {
+        // Never reached this hypervisor; the status line says why.
+        let text = format!("Could not connect to {}, retrying", session.uri);
+        let header = Paragraph::new(text)
+            .style(
+                Style::new()
+                    .fg(app.colors.crashed_fg)
+                    .bg(app.colors.buffer_bg),
+            )
+            .centered()
+            .block(block);
+        f.render_widget(header, area);
+        return;
+    };
+
+    let text = format!(
+        "{} | {} | {} {} | {} cpus ({} sockets x {} cores x {} threads, {}) | {} memory",
+        host.uri,
+        host.hostname,
+        host.hypervisor,
+        host.version,
+        host.cpus,
+        host.sockets,
+        host.cores,
+        host.threads,
+        host.cpu_model,
+        app.units.format(host.memory * 1024),
+    );
+
+    // Allocation beyond the host's capacity is highlighted as overcommit.
+    let (vcpus, mem) = session.allocated();
+    let usage_style = |used: u64, total: u64| {
+        if used > total {
+            Style::new().fg(app.colors.crashed_fg)
+        } else {
+            Style::new()
+        }
+    };
+    let host_cpu = match session.host_cpu_usage {
+        Some(usage) => format!("host cpu: {:.1}% | ", usage),
+        None => String::new(),
+    };
+    let host_memory = match session.host_memory {
+        Some(memory) => format!(
+            "host memory: {} used, {} free | ",
+            app.units.format(memory.used() * 1024),
+            app.units.format(memory.free * 1024)
+        ),
+        None => String::new(),
+    };
+    let allocated = Line::from(vec![
+        Span::raw(host_cpu),
+        Span::raw(host_memory),
+        Span::raw("running vms: "),
+        Span::styled(
+            format!("{}/{} vcpus", vcpus, host.cpus),
+            usage_style(vcpus, host.cpus.into()),
+        ),
+        Span::raw(" | "),
+        Span::styled(
+            format!(
+                "{} / {} memory",
+                app.units.format(mem * 1024),
+                app.units.format(host.memory * 1024)
+            ),
+            usage_style(mem, host.memory),
+        ),
+    ]);
+
+    let header = Paragraph::new(vec![Line::from(text), allocated])
+        .style(Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg))
+        .centered()
+        .block(block);
+    f.render_widget(header, area);
+}
+
+/// Title of the host box: a tab per hypervisor when there are several, with
+/// the current one highlighted and unreachable ones in the error color.
+fn tab_titles(app: &App) -> Line<'static> {
+    if app.sessions.len() == 1 {
+        return Line::from("Host");
+    }
+
+    let mut spans = vec![];
+    for (i, session) in app.sessions.iter().enumerate() {
+        let mut style = Style::new();
+        if session.disconnected.is_some() {
+            style = style.fg(app.colors.crashed_fg);
+        }
+        if i == app.current {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+        spans.push(Span::styled(format!(" {} ", session.uri), style));
+        spans.push(Span::raw("|"));
+    }
+    spans.pop();
+    Line::from(spans)
+}
+
+fn render_overview(f: &mut Frame, app: &mut App, area: Rect) {
+    let block = Block::bordered()
+        .title("VM statistics")
+        .border_type(BorderType::Thick)
+        .border_style(pane_border(&app.colors, app.focus == Focus::Overview));
+
+    let index = match app.selected_index() {
+        Some(i) => i,
+        None => {
+            f.render_widget(Paragraph::new("No VM selected").block(block), area);
+            return;
+        }
+    };
+    // Handed back once the overview is drawn, as it borrows the session.
+    let mut scroll = mem::take(&mut app.overview_scroll);
+    let session = app.session();
+
+    let mut lines = vec![
+        Line::from(format!("Name: {}", session.table_data[index].name)),
+        Line::from(format!("Status: {}", session.table_data[index].status)),
+        Line::from(format!("UUID: {}", session.metrics[index].uuid)),
+    ];
+    let metadata = &session.metrics[index].metadata;
+    if !metadata.title.is_empty() {
+        lines.push(Line::from(format!("Title: {}", metadata.title)));
+    }
+    if !metadata.description.is_empty() {
+        lines.push(Line::from(format!("Description: {}", metadata.description)));
+    }
+    lines.extend([
         Line::from(format!(
-            "MB upload: {:.2}",
-            app.metrics[index].net_rx as f64 / 1024.0
+            "Type: {} {} ({}), {}",
+            session.metrics[index].os.os_type,
+            session.metrics[index].os.arch,
+            session.metrics[index].os.machine,
+            if session.metrics[index].persistent {
+                "persistent"
+            } else {
+                "transient"
+            }
         )),
         Line::from(format!(
-            "MB download: {:.2}",
-            app.metrics[index].net_tx as f64 / 1024.0
+            "Autostart: {}",
+            if session.metrics[index].autostart {
+                "yes"
+            } else {
+                "no"
+            }
         )),
-        Line::from(format!("Disk: {}", app.metrics[index].disk_name)),
-        Line::from(format!("path: {}", app.metrics[index].disk_path)),
         Line::from(format!(
-            "MB read: {}",
-            app.metrics[index].disk_rx as f64 / 1024.0
+            "Uptime: {}",
+            session.metrics[index]
+                .running_since
+                .map_or(String::from("-"), |since| format_duration(since.elapsed()))
         )),
         Line::from(format!(
-            "MB written: {}",
-            app.metrics[index].disk_wx as f64 / 1024.0
+            "CPU Usage: {}{} ({} of {} max vCPUs)",
+            session.table_data[index].cpu_usage,
+            if app.per_vcpu { " per vCPU" } else { "" },
+            session.metrics[index].vcpus,
+            session.metrics[index].vcpus_max
         )),
+    ]);
+    if !session.metrics[index].vcpu_stats.is_empty() {
+        let usage: Vec<String> = session.metrics[index]
+            .vcpu_stats
+            .iter()
+            .enumerate()
+            .map(|(i, vcpu)| format!("{}: {:.1}%", i, vcpu.usage))
+            .collect();
+        lines.push(Line::from(format!("- vCPUs: {}", usage.join(" | "))));
+    }
+    lines.extend(vcpu_pin_line(app, index));
+    lines.extend([
+        Line::from(format!(
+            "Mem Usage: {} (balloon target {} of {} max)",
+            app.units.format(session.metrics[index].mem_usage() * 1024),
+            app.units.format(session.metrics[index].mem_current * 1024),
+            app.units.format(session.metrics[index].mem_max * 1024)
+        )),
+        Line::from(format!(
+            "Network: ↓ {}/s ↑ {}/s ({} downloaded, {} uploaded)",
+            app.units.format(session.metrics[index].net_rx_rate as u64),
+            app.units.format(session.metrics[index].net_tx_rate as u64),
+            app.units.format(session.metrics[index].net_rx),
+            app.units.format(session.metrics[index].net_tx)
+        )),
+    ]);
+    lines.extend(guest_address_lines(app, index));
+    for iface in &session.metrics[index].interfaces {
+        lines.push(Line::from(format!(
+            "- {}: {} downloaded, {} uploaded",
+            iface.name,
+            app.units.format(iface.rx_bytes),
+            app.units.format(iface.tx_bytes)
+        )));
+    }
+    lines.push(Line::from(format!(
+        "Disk: read {}/s write {}/s ({} read, {} written)",
+        app.units.format(session.metrics[index].disk_rx_rate as u64),
+        app.units.format(session.metrics[index].disk_wx_rate as u64),
+        app.units.format(session.metrics[index].disk_rx),
+        app.units.format(session.metrics[index].disk_wx)
+    )));
+    for disk in &session.metrics[index].disks {
+        lines.push(Line::from(format!(
+            "- {} ({}): {} read, {} written, {} of {} allocated ({} on the host)",
+            disk.name,
+            disk.path,
+            app.units.format(disk.rd_bytes),
+            app.units.format(disk.wr_bytes),
+            app.units.format(disk.allocation),
+            app.units.format(disk.capacity),
+            app.units.format(disk.physical)
+        )));
+    }
+    lines.push(io_limits_line(app, index));
+
+    // The CPU history takes the bottom of the box, below the stats.
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let [text_area, history_area] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(inner);
+
+    // Lines wrap at word boundaries, so the content is measured in rows of
+    // the pane the way the paragraph lays them out.
+    let overview = Paragraph::new(lines).wrap(Wrap { trim: true });
+    let rows = overview.line_count(text_area.width);
+    scroll.resize(rows, text_area.height.into());
+    f.render_widget(overview.scroll((scroll.offset as u16, 0)), text_area);
+    if rows > scroll.page_size {
+        f.render_stateful_widget(
+            Scrollbar::default()
+                .orientation(ScrollbarOrientation::VerticalRight)
+                .begin_symbol(None)
+                .end_symbol(None),
+            text_area,
+            &mut scroll.state,
+        );
+    }
+
+    let domain = &session.metrics[index];
+    let [cpu_area, mem_area] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .areas(history_area);
+
+    let history = history_of(&session.cpu_history, &domain.uuid);
+    // Scale to all vCPUs busy, so a flat line at the top means the VM is
+    // maxed out.
+    let max = domain.vcpus.max(1) * 100;
+    let sparkline = Sparkline::default()
+        .block(Block::new().title(format!("CPU history (last {} samples)", HISTORY_LEN)))
+        .data(&history)
+        .max(max)
+        .style(Style::new().fg(app.colors.running_fg));
+    f.render_widget(sparkline, cpu_area);
+
+    let history = history_of(&session.mem_history, &domain.uuid);
+    // Scale to the memory assigned to the guest, when the balloon reports it.
+    let max = history
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(0)
+        .max(domain.mem_current)
+        .max(1);
+    let sparkline = Sparkline::default()
+        .block(Block::new().title(format!(
+            "Memory history (max {})",
+            app.units.format(max * 1024)
+        )))
+        .data(&history)
+        .max(max)
+        .style(Style::new().fg(app.colors.running_fg));
+    f.render_widget(sparkline, mem_area);
+    app.overview_scroll = scroll;
+}
+
+/// Header of a table column, telling the CPU usage column apart while it is
+/// shown per vCPU.
+fn column_title(column: Column, per_vcpu: bool) -> &'static str {
+    if per_vcpu && column == Column::Cpu {
+        "cpu / vcpu"
+    } else {
+        column.title()
+    }
+}
+
+/// Samples kept for the VM with `uuid`, oldest first.
+fn history_of(histories: &HashMap<String, VecDeque<u64>>, uuid: &str) -> Vec<u64> {
+    histories
+        .get(uuid)
+        .map(|h| h.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+fn render_text_view(f: &mut Frame, colors: &TableColors, view: &mut TextView, area: Rect) {
+    let block = Block::bordered()
+        .title(format!("{} (esc to close)", view.title))
+        .border_type(BorderType::Thick)
+        .border_style(Style::new().fg(colors.footer_border_color));
+    view.page_size = usize::from(block.inner(area).height).max(1);
+    view.scroll_to(view.offset);
+
+    let text = Paragraph::new(
+        view.lines
+            .iter()
+            .map(|l| Line::from(l.as_str()))
+            .collect::<Vec<_>>(),
+    )
+    .style(Style::new().fg(colors.row_fg).bg(colors.buffer_bg))
+    .scroll((view.offset as u16, 0))
+    .block(block);
+
+    f.render_widget(Clear, area);
+    f.render_widget(text, area);
+    f.render_stateful_widget(
+        Scrollbar::default()
+            .orientation(ScrollbarOrientation::VerticalRight)
+            .begin_symbol(None)
+            .end_symbol(None),
+        area.inner(&Margin {
+            vertical: 1,
+            horizontal: 0,
+        }),
+        &mut view.scroll_state,
+    );
+}
+
+fn render_storage(
+    f: &mut Frame,
+    colors: &TableColors,
+    units: Units,
+    view: &mut StorageView,
+    area: Rect,
+) {
+    let (title, header, rows, state) = match &view.volumes {
+        Some((pool, volumes)) => (
+            format!("{} volumes (esc to go back)", pool),
+            ["name", "type", "capacity", "allocation", "path"],
+            volumes
+                .iter()
+                .map(|vol| {
+                    Row::new([
+                        vol.name.clone(),
+                        vol.kind.to_string(),
+                        units.format(vol.capacity),
+                        units.format(vol.allocation),
+                        vol.path.clone(),
+                    ])
+                })
+                .collect::<Vec<_>>(),
+            &mut view.volume_state,
+        ),
+        None => (
+            String::from("storage pools | (enter) volumes | (esc) close"),
+            ["name", "state", "capacity", "allocation", "available"],
+            view.pools
+                .iter()
+                .map(|pool| {
+                    let name = if pool.autostart {
+                        format!("{} (autostart)", pool.name)
+                    } else {
+                        pool.name.clone()
+                    };
+                    Row::new([
+                        name,
+                        pool.state.to_string(),
+                        units.format(pool.capacity),
+                        units.format(pool.allocation),
+                        units.format(pool.available),
+                    ])
+                })
+                .collect::<Vec<_>>(),
+            &mut view.pool_state,
+        ),
+    };
+
+    let block = Block::bordered()
+        .title(title)
+        .border_type(BorderType::Thick)
+        .border_style(Style::new().fg(colors.footer_border_color));
+    let header = header
+        .into_iter()
+        .collect::<Row>()
+        .style(Style::new().fg(colors.header_fg).bg(colors.header_bg));
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(2),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Fill(3),
+        ],
+    )
+    .header(header)
+    .style(Style::new().fg(colors.row_fg).bg(colors.buffer_bg))
+    .highlight_style(
+        Style::default()
+            .add_modifier(Modifier::REVERSED)
+            .fg(colors.selected_style_fg),
+    )
+    .block(block);
+
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(table, area, state);
+}
+
+fn render_networks(f: &mut Frame, colors: &TableColors, view: &mut NetworkView, area: Rect) {
+    let rows = view.networks.iter().map(|net| {
+        let state = if net.active { "active" } else { "inactive" };
+        let autostart = if net.autostart { "yes" } else { "no" };
+        Row::new([
+            net.name.clone(),
+            state.to_string(),
+            net.bridge.clone(),
+            autostart.to_string(),
+            net.vms.join(", "),
+        ])
+    });
+
+    let block = Block::bordered()
+        .title("networks (esc to close)")
+        .border_type(BorderType::Thick)
+        .border_style(Style::new().fg(colors.footer_border_color));
+    let header = ["name", "state", "bridge", "autostart", "vms"]
+        .into_iter()
+        .collect::<Row>()
+        .style(Style::new().fg(colors.header_fg).bg(colors.header_bg));
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(1),
+            Constraint::Length(10),
+            Constraint::Length(16),
+            Constraint::Length(10),
+            Constraint::Fill(2),
+        ],
+    )
+    .header(header)
+    .style(Style::new().fg(colors.row_fg).bg(colors.buffer_bg))
+    .highlight_style(
+        Style::default()
+            .add_modifier(Modifier::REVERSED)
+            .fg(colors.selected_style_fg),
+    )
+    .block(block);
+
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(table, area, &mut view.table_state);
+}
+
+fn render_console(f: &mut Frame, colors: &TableColors, console: &Console, area: Rect) {
+    let title = match &console.closed {
+        Some(reason) => format!(
+            "{} console closed: {} (esc to close)",
+            console.vm_name, reason
+        ),
+        None => format!("{} console (ctrl-] to detach)", console.vm_name),
+    };
+    let block = Block::bordered()
+        .title(title)
+        .border_type(BorderType::Thick)
+        .border_style(Style::new().fg(colors.footer_border_color));
+
+    // Follow the output, showing as many of the last lines as fit.
+    let height = usize::from(block.inner(area).height);
+    let start = console.lines.len().saturating_sub(height);
+    let text = Paragraph::new(
+        console.lines[start..]
+            .iter()
+            .map(|l| Line::from(l.as_str()))
+            .collect::<Vec<_>>(),
+    )
+    .style(Style::new().fg(colors.row_fg).bg(colors.buffer_bg))
+    .block(block);
+
+    f.render_widget(Clear, area);
+    f.render_widget(text, area);
+}
+
+fn render_snapshots(
+    f: &mut Frame,
+    colors: &TableColors,
+    vm_name: &str,
+    snapshots: &[SnapshotInfo],
+    list_state: &mut ListState,
+) {
+    let area = centered_rect(f.size().width * 4 / 5, f.size().height * 3 / 5, f.size());
+    let block = Block::bordered()
+        .title(format!(
+            "{} snapshots | (enter) revert | (esc) close",
+            vm_name
+        ))
+        .border_type(BorderType::Double)
+        .border_style(Style::new().fg(colors.footer_border_color));
+
+    f.render_widget(Clear, area);
+    if snapshots.is_empty() {
+        f.render_widget(
+            Paragraph::new("No snapshots")
+                .style(Style::new().fg(colors.row_fg).bg(colors.buffer_bg))
+                .block(block),
+            area,
+        );
+        return;
+    }
+
+    let items = snapshots.iter().map(|snapshot| {
+        let created = DateTime::from_timestamp(snapshot.created, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| String::from("unknown"));
+        ListItem::new(format!(
+            "{}  {}  [{}]  {}",
+            created, snapshot.name, snapshot.state, snapshot.description
+        ))
+    });
+    let list = List::new(items)
+        .style(Style::new().fg(colors.row_fg).bg(colors.buffer_bg))
+        .highlight_style(
+            Style::default()
+                .add_modifier(Modifier::REVERSED)
+                .fg(colors.selected_style_fg),
+        )
+        .block(block);
+
+    f.render_stateful_widget(list, area, list_state);
+}
+
+fn render_columns(
+    f: &mut Frame,
+    colors: &TableColors,
+    columns: &[Column],
+    list_state: &mut ListState,
+) {
+    let height = Column::ALL.len() as u16 + 2;
+    let area = centered_rect(50, height, f.size());
+    let block = Block::bordered()
+        .title("Columns | (space) show / hide | (esc) close")
+        .border_type(BorderType::Double)
+        .border_style(Style::new().fg(colors.footer_border_color));
+
+    let items = Column::ALL.into_iter().map(|column| {
+        let mark = if columns.contains(&column) { "x" } else { " " };
+        ListItem::new(format!("[{}] {}", mark, column.title()))
+    });
+    let list = List::new(items)
+        .style(Style::new().fg(colors.row_fg).bg(colors.buffer_bg))
+        .highlight_style(
+            Style::default()
+                .add_modifier(Modifier::REVERSED)
+                .fg(colors.selected_style_fg),
+        )
+        .block(block);
+
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, list_state);
+}
+
+fn render_form(f: &mut Frame, colors: &TableColors, form: &Form) {
+    let mut lines = vec![];
+    for (i, field) in form.fields.iter().enumerate() {
+        let label = Span::raw(format!("{}: ", field.label));
+        let value = if i == form.focus {
+            Span::raw(format!("{}█", field.value))
+        } else if field.value.is_empty() {
+            Span::styled(
+                field.placeholder.as_str(),
+                Style::new().fg(colors.stopped_fg),
+            )
+        } else {
+            Span::raw(field.value.as_str())
+        };
+        lines.push(Line::from(vec![label, value]));
+    }
+    if let Some(note) = form.note {
+        lines.push(Line::from(""));
+        lines.push(Line::from(note));
+    }
+    if let Some(error) = &form.error {
+        lines.push(Line::from(""));
+        lines.push(Line::styled(
+            error.as_str(),
+            Style::new().fg(colors.crashed_fg),
+        ));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(
+        "(tab) next field | (enter) submit | (esc) cancel",
+    ));
+
+    let area = centered_rect(70, lines.len() as u16 + 2, f.size());
+    let popup = Paragraph::new(lines)
+        .style(Style::new().fg(colors.row_fg).bg(colors.buffer_bg))
+        .block(
+            Block::bordered()
+                .title(form.title())
+                .border_type(BorderType::Double)
+                .border_style(Style::new().fg(colors.footer_border_color)),
+        )
+        .wrap(Wrap { trim: false });
+
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+fn render_confirm(f: &mut Frame, colors: &TableColors, question: &str) {
+    let area = centered_rect(60, 8, f.size());
+    let popup = Paragraph::new(vec![
+        Line::from(question.to_string()),
+        Line::from(""),
+        Line::from("(y) yes | (n) no"),
     ])
+    .style(Style::new().fg(colors.row_fg).bg(colors.buffer_bg))
+    .centered()
     .block(
         Block::bordered()
-            .title("VM statistics")
-            .border_type(BorderType::Thick)
-            .border_style(Style::new().fg(app.colors.footer_border_color)),
+            .title("Confirm")
+            .border_type(BorderType::Double)
+            .border_style(Style::new().fg(colors.footer_border_color)),
     )
     .wrap(Wrap { trim: true });
 
-    f.render_widget(overview, area);
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Returns a `width` x `height` rect centered in `area`, clamped to fit.
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let [area] = Layout::vertical([Constraint::Length(height)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::horizontal([Constraint::Length(width)])
+        .flex(Flex::Center)
+        .areas(area);
+    area
+}
+
+/// Lists the guest agent addresses of the VM at `index` in the metrics of
+/// the current session.
+fn guest_address_lines(app: &App, index: usize) -> Vec<Line<'static>> {
+    let session = app.session();
+    let domain = &session.metrics[index];
+    if domain.status != DomainState::Running {
+        return vec![Line::from("Addresses: -")];
+    }
+
+    match &session.guest_addresses {
+        Some((name, Some(addresses))) if *name == domain.name => {
+            if addresses.is_empty() {
+                return vec![Line::from("Addresses: none")];
+            }
+            let mut lines = vec![Line::from("Addresses:")];
+            for addr in addresses {
+                lines.push(Line::from(format!(
+                    "- {}: {}/{}",
+                    addr.interface, addr.address, addr.prefix
+                )));
+            }
+            lines
+        }
+        Some((name, None)) if *name == domain.name => {
+            vec![Line::from("Addresses: agent unavailable")]
+        }
+        // Not queried yet since the selection changed.
+        _ => vec![Line::from("Addresses: ...")],
+    }
+}
+
+/// Shows which host CPUs the vCPUs of the VM at `index` in the metrics of
+/// the current session are pinned to, if any of them is.
+fn vcpu_pin_line(app: &App, index: usize) -> Option<Line<'static>> {
+    let session = app.session();
+    let domain = &session.metrics[index];
+    let host_cpus = session.host.as_ref()?.cpus as usize;
+    let (name, pins) = session.vcpu_pins.as_ref()?;
+    if domain.status != DomainState::Running || *name != domain.name {
+        return None;
+    }
+    // A vCPU allowed on every host CPU isn't pinned.
+    if pins.iter().all(|cpus| cpus.len() >= host_cpus) {
+        return Some(Line::from("- Pinning: none"));
+    }
+
+    let pins: Vec<String> = pins
+        .iter()
+        .enumerate()
+        .map(|(vcpu, cpus)| format!("{}→{}", vcpu, format_cpuset(cpus)))
+        .collect();
+    Some(Line::from(format!("- Pinning: {}", pins.join(" | "))))
+}
+
+/// Shows the bandwidth caps of the VM at `index` in the metrics of the
+/// current session.
+fn io_limits_line(app: &App, index: usize) -> Line<'static> {
+    let session = app.session();
+    let domain = &session.metrics[index];
+    if domain.status != DomainState::Running {
+        return Line::from("Limits: -");
+    }
+
+    match &session.io_limits {
+        Some((name, limits)) if *name == domain.name => {
+            let limit = |bytes: Option<u64>| {
+                bytes.map_or(String::from("none"), |b| {
+                    format!("{}/s", app.units.format(b))
+                })
+            };
+            Line::from(format!(
+                "Limits: disk {}, network {}",
+                limit(limits.disk),
+                limit(limits.net.map(|kib| kib * 1024))
+            ))
+        }
+        // Not queried yet since the selection changed.
+        _ => Line::from("Limits: ..."),
+    }
+}
+
+/// Frame of the spinner shown while connecting, turning with the clock.
+fn spinner() -> char {
+    const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    FRAMES[(millis / 100) as usize % FRAMES.len()]
+}
+
+/// Formats a duration as its two largest units, e.g. `2h 14m`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, mins) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60);
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m {}s", mins, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
 }
@@ -0,0 +1,64 @@
+use serde::Deserialize;
+
+/// How byte counts are shown: memory, disk and network figures alike.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    /// The largest binary unit that keeps the value above 1.
+    #[default]
+    Auto,
+    Mib,
+    Gib,
+    /// The largest decimal unit (kB, MB, GB, ...) that keeps the value
+    /// above 1.
+    Decimal,
+}
+
+impl Units {
+    /// Cycles auto -> MiB -> GiB -> decimal -> auto.
+    pub fn cycle(self) -> Self {
+        match self {
+            Self::Auto => Self::Mib,
+            Self::Mib => Self::Gib,
+            Self::Gib => Self::Decimal,
+            Self::Decimal => Self::Auto,
+        }
+    }
+
+    /// Name of the setting in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Mib => "mib",
+            Self::Gib => "gib",
+            Self::Decimal => "decimal",
+        }
+    }
+
+    /// Formats a byte count in these units.
+    pub fn format(self, bytes: u64) -> String {
+        match self {
+            Self::Auto => scaled(bytes, 1024.0, &["B", "KiB", "MiB", "GiB", "TiB"]),
+            Self::Mib => format!("{:.2} MiB", bytes as f64 / (1u64 << 20) as f64),
+            Self::Gib => format!("{:.2} GiB", bytes as f64 / (1u64 << 30) as f64),
+            Self::Decimal => scaled(bytes, 1000.0, &["B", "kB", "MB", "GB", "TB"]),
+        }
+    }
+}
+
+/// Formats a byte count using the largest of `units`, each `step` times the
+/// one before, that keeps the value above 1.
+fn scaled(bytes: u64, step: f64, units: &[&str]) -> String {
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= step && unit < units.len() - 1 {
+        value /= step;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.2} {}", value, units[unit])
+    }
+}
@@ -1,82 +1,482 @@
 use core::slice;
-use std::ffi::CStr;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::ffi::{c_int, c_uint, c_ulong, CStr, CString};
+use std::fmt;
+use std::ptr;
 use std::time::Instant;
 
 use chrono::prelude::*;
+use serde::{Serialize, Serializer};
 
 use virt::connect::Connect;
 use virt::domain::{Domain, DomainStatsRecord};
 use virt::domain_snapshot::DomainSnapshot;
-use virt::error::Error;
+use virt::error::{Error, ErrorNumber};
+use virt::storage_pool::StoragePool;
+use virt::storage_vol::StorageVol;
+use virt::stream::Stream;
 use virt::sys::{
-    virDomainGetID, virDomainGetName, virDomainStatsRecord,
+    virDomainGetAutostart, virDomainGetID, virDomainGetName, virDomainGetUUIDString,
+    virDomainGetVcpuPinInfo, virDomainHasManagedSaveImage, virDomainIsPersistent, virDomainMigrate,
+    virDomainOpenConsole, virDomainSetBlockIoTune, virDomainSetInterfaceParameters,
+    virDomainStatsRecord, virNodeCPUStats, virNodeGetCPUStats, virNodeGetMemoryStats,
+    virNodeMemoryStats, virStreamRecv, virStreamSend, virTypedParameter, virTypedParameterPtr,
+    virTypedParamsAddUInt, virTypedParamsAddULLong, virTypedParamsFree,
     VIR_CONNECT_GET_ALL_DOMAINS_STATS_ACTIVE, VIR_CONNECT_GET_ALL_DOMAINS_STATS_INACTIVE,
-    VIR_DOMAIN_SNAPSHOT_CREATE_DISK_ONLY, VIR_DOMAIN_STATS_BALLOON, VIR_DOMAIN_STATS_BLOCK,
-    VIR_DOMAIN_STATS_CPU_TOTAL, VIR_DOMAIN_STATS_INTERFACE, VIR_DOMAIN_STATS_STATE,
-    VIR_DOMAIN_STATS_VCPU,
+    VIR_DOMAIN_AFFECT_LIVE, VIR_DOMAIN_BANDWIDTH_IN_AVERAGE, VIR_DOMAIN_BANDWIDTH_OUT_AVERAGE,
+    VIR_DOMAIN_BLOCKED, VIR_DOMAIN_BLOCK_IOTUNE_TOTAL_BYTES_SEC, VIR_DOMAIN_CONSOLE_SAFE,
+    VIR_DOMAIN_CRASHED, VIR_DOMAIN_INTERFACE_ADDRESSES_SRC_AGENT, VIR_DOMAIN_MEM_LIVE,
+    VIR_DOMAIN_NOSTATE, VIR_DOMAIN_PAUSED, VIR_DOMAIN_PMSUSPENDED, VIR_DOMAIN_RUNNING,
+    VIR_DOMAIN_SHUTDOWN, VIR_DOMAIN_SHUTOFF, VIR_DOMAIN_SNAPSHOT_CREATE_DISK_ONLY,
+    VIR_DOMAIN_STATS_BALLOON, VIR_DOMAIN_STATS_BLOCK, VIR_DOMAIN_STATS_CPU_TOTAL,
+    VIR_DOMAIN_STATS_INTERFACE, VIR_DOMAIN_STATS_STATE, VIR_DOMAIN_STATS_VCPU,
+    VIR_DOMAIN_UNDEFINE_CHECKPOINTS_METADATA, VIR_DOMAIN_UNDEFINE_MANAGED_SAVE,
+    VIR_DOMAIN_UNDEFINE_NVRAM, VIR_DOMAIN_UNDEFINE_SNAPSHOTS_METADATA, VIR_DOMAIN_VCPU_LIVE,
+    VIR_DOMAIN_XML_INACTIVE, VIR_DOMAIN_XML_SECURE, VIR_KEYCODE_SET_LINUX, VIR_MIGRATE_LIVE,
+    VIR_MIGRATE_PERSIST_DEST, VIR_MIGRATE_UNDEFINE_SOURCE, VIR_NODE_CPU_STATS_ALL_CPUS,
+    VIR_NODE_MEMORY_STATS_ALL_CELLS, VIR_STREAM_NONBLOCK, VIR_TYPED_PARAM_BOOLEAN,
+    VIR_TYPED_PARAM_INT, VIR_TYPED_PARAM_LLONG, VIR_TYPED_PARAM_STRING, VIR_TYPED_PARAM_UINT,
+    VIR_TYPED_PARAM_ULLONG, VIR_UUID_STRING_BUFLEN,
 };
 
-#[derive(Debug)]
+use crate::config::{DEFAULT_URI, SESSION_URI};
+
+/// Domain lifecycle state, mirroring libvirt's `virDomainState`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DomainState {
+    NoState,
+    Running,
+    Blocked,
+    Paused,
+    Shutdown,
+    ShutOff,
+    Crashed,
+    PmSuspended,
+    Unknown,
+}
+
+impl DomainState {
+    fn from_raw(state: u32) -> Self {
+        match state {
+            VIR_DOMAIN_NOSTATE => Self::NoState,
+            VIR_DOMAIN_RUNNING => Self::Running,
+            VIR_DOMAIN_BLOCKED => Self::Blocked,
+            VIR_DOMAIN_PAUSED => Self::Paused,
+            VIR_DOMAIN_SHUTDOWN => Self::Shutdown,
+            VIR_DOMAIN_SHUTOFF => Self::ShutOff,
+            VIR_DOMAIN_CRASHED => Self::Crashed,
+            VIR_DOMAIN_PMSUSPENDED => Self::PmSuspended,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Whether the domain has a running qemu process behind it.
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            Self::Running | Self::Blocked | Self::Paused | Self::Shutdown | Self::PmSuspended
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NoState => "no state",
+            Self::Running => "running",
+            Self::Blocked => "idle",
+            Self::Paused => "paused",
+            Self::Shutdown => "in shutdown",
+            Self::ShutOff => "shut off",
+            Self::Crashed => "crashed",
+            Self::PmSuspended => "pmsuspended",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl Serialize for DomainState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl fmt::Display for DomainState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Vcpu {
+    /// CPU time used by the vCPU, in nanoseconds.
+    pub time: u64,
+    /// Percentage of one host CPU used since the previous sample.
+    pub usage: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct NetInterface {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BlockDevice {
+    pub name: String,
+    pub path: String,
+    pub rd_bytes: u64,
+    pub wr_bytes: u64,
+    /// Size of the disk as the guest sees it.
+    pub capacity: u64,
+    /// Bytes of the image actually in use on the host, less than `capacity`
+    /// for thin provisioned disks.
+    pub allocation: u64,
+    /// Size of the backing file or device on the host.
+    pub physical: u64,
+}
+
+/// A snapshot of a domain, as listed in the snapshot browser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub description: String,
+    /// Domain state the snapshot was taken in (e.g. `running`, `disk-snapshot`).
+    pub state: String,
+    /// Creation time, in seconds since the unix epoch.
+    pub created: i64,
+}
+
+/// Platform a domain emulates, from its XML.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OsInfo {
+    /// OS type, `hvm` for fully virtualized guests.
+    pub os_type: String,
+    pub arch: String,
+    /// Machine type, e.g. `pc-q35-8.2`.
+    pub machine: String,
+}
+
+/// Human readable title and description a domain may carry in its XML,
+/// empty if it doesn't.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub title: String,
+    pub description: String,
+}
+
+/// An IP address the guest agent reports for one of the guest's interfaces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestAddress {
+    pub interface: String,
+    pub address: String,
+    pub prefix: u64,
+}
+
+/// Facts about the hypervisor host, fetched once on connect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostInfo {
+    pub uri: String,
+    pub hostname: String,
+    /// Hypervisor driver, e.g. `QEMU`.
+    pub hypervisor: String,
+    /// Hypervisor version as `major.minor.release`.
+    pub version: String,
+    pub cpu_model: String,
+    /// Number of active host CPUs.
+    pub cpus: u32,
+    /// Number of CPU sockets, over all NUMA nodes.
+    pub sockets: u32,
+    pub cores: u32,
+    pub threads: u32,
+    /// Host memory in KiB.
+    pub memory: u64,
+}
+
+#[derive(Debug, Serialize)]
 pub struct VmMetrics {
     pub name: String,
-    pub status: bool,
+    /// Identifies the domain for good, unlike its name or runtime id.
+    pub uuid: String,
+    /// Read from the domain XML by the collector, which only does so once
+    /// per domain.
+    #[serde(skip)]
+    pub os: OsInfo,
+    /// Read along with `os`.
+    #[serde(skip)]
+    pub metadata: Metadata,
+    pub status: DomainState,
     pub id: u32,
+    /// Does the domain outlive being shut off? Transient domains are gone
+    /// once they stop.
+    pub persistent: bool,
+    /// Is the domain started along with the host?
+    pub autostart: bool,
+    /// Does the domain have a managed save image it will be restored from
+    /// on the next start?
+    pub managed_save: bool,
+    #[serde(skip)]
     pub timestamp: Instant,
+    /// When the domain was first seen running this session, `None` while it
+    /// is shut off. Libvirt doesn't report boot time, so for domains already
+    /// running when vmgr started this undercounts the uptime.
+    #[serde(skip)]
+    pub running_since: Option<Instant>,
     pub cpu_time: u64,
+    /// Percentage of one host CPU used since the previous sample.
+    pub cpu_usage: f64,
+    /// Number of vCPUs currently assigned.
+    pub vcpus: u64,
+    /// Number of vCPUs the domain can be given without a restart.
+    pub vcpus_max: u64,
+    /// Per-vCPU usage, empty while the domain is shut off.
+    pub vcpu_stats: Vec<Vcpu>,
+    /// Memory currently assigned to the guest, in KiB. This is the balloon
+    /// target, which the guest may take a while to reach.
+    pub mem_current: u64,
+    /// Memory the guest can be ballooned up to, in KiB.
+    pub mem_max: u64,
     pub mem_rss: u64,
     pub mem_cache: u64,
-    pub net_name: String,
+    pub interfaces: Vec<NetInterface>,
+    /// Bytes received, summed over all interfaces.
     pub net_rx: u64,
+    /// Bytes transmitted, summed over all interfaces.
     pub net_tx: u64,
-    pub disk_name: String,
-    pub disk_path: String,
+    /// Bytes per second received since the previous sample.
+    pub net_rx_rate: f64,
+    /// Bytes per second transmitted since the previous sample.
+    pub net_tx_rate: f64,
+    pub disks: Vec<BlockDevice>,
+    /// Bytes read, summed over all block devices.
     pub disk_rx: u64,
+    /// Bytes written, summed over all block devices.
     pub disk_wx: u64,
+    /// Bytes per second read since the previous sample.
+    pub disk_rx_rate: f64,
+    /// Bytes per second written since the previous sample.
+    pub disk_wx_rate: f64,
 }
 
 impl Default for VmMetrics {
     fn default() -> Self {
         Self {
             name: String::from("unknown"),
-            status: false,
+            uuid: String::new(),
+            os: OsInfo::default(),
+            metadata: Metadata::default(),
+            status: DomainState::Unknown,
             id: 0,
+            persistent: false,
+            autostart: false,
+            managed_save: false,
             timestamp: Instant::now(),
+            running_since: None,
             cpu_time: 0,
+            cpu_usage: 0.0,
+            vcpus: 0,
+            vcpus_max: 0,
+            vcpu_stats: vec![],
+            mem_current: 0,
+            mem_max: 0,
             mem_rss: 0,
             mem_cache: 0,
-            net_name: String::from("unknown"),
+            interfaces: vec![],
             net_rx: 0,
             net_tx: 0,
-            disk_name: String::from("unknown"),
-            disk_path: String::from("unknown"),
+            net_rx_rate: 0.0,
+            net_tx_rate: 0.0,
+            disks: vec![],
             disk_rx: 0,
             disk_wx: 0,
+            disk_rx_rate: 0.0,
+            disk_wx_rate: 0.0,
         }
     }
 }
 
-pub fn connect(uri: &str) -> Connect {
-    let conn = match Connect::open(uri) {
-        Ok(c) => c,
-        Err(e) => panic!("failed to connect to hypervisor: {}", e),
-    };
+impl VmMetrics {
+    /// Memory the guest takes up on the host, in KiB: its resident set plus
+    /// the disk caches it holds. This is what the memory column shows.
+    pub fn mem_usage(&self) -> u64 {
+        self.mem_rss + self.mem_cache
+    }
+}
+
+pub fn connect(uri: &str) -> Result<Connect, Error> {
+    Connect::open(uri)
+}
+
+/// Connects with read access only, which is all some users have on shared
+/// hypervisors. Libvirt refuses lifecycle actions and guest agent queries on
+/// such a connection.
+pub fn connect_read_only(uri: &str) -> Result<Connect, Error> {
+    Connect::open_read_only(uri)
+}
 
-    return conn;
+/// Explains why connecting to `uri` failed. Being refused `qemu:///system`
+/// is most often down to not running as root nor being in the libvirt
+/// group, so the per-user hypervisor is suggested instead of leaving the
+/// bare libvirt error.
+pub fn connect_error(uri: &str, e: &Error) -> String {
+    let denied = matches!(
+        e.code(),
+        ErrorNumber::AuthFailed
+            | ErrorNumber::AuthUnavailable
+            | ErrorNumber::AccessDenied
+            | ErrorNumber::OperationDenied
+    ) || e.message().contains("Permission denied");
+    if denied && uri == DEFAULT_URI {
+        format!(
+            "{} (run as root, join the libvirt group, or use --connect {} for your own vms)",
+            e, SESSION_URI
+        )
+    } else {
+        e.to_string()
+    }
 }
 
+/// Closes a connection that is done with. A failure to close is ignored: it
+/// usually means the connection was already lost, and it is being torn down
+/// either way.
 pub fn disconnect(conn: &mut Connect) {
-    if let Err(e) = conn.close() {
-        panic!("failed to disconnect from hypervisor: {}", e);
+    let _ = conn.close();
+}
+
+pub fn get_host_info(conn: &Connect) -> Result<HostInfo, Error> {
+    let node = conn.get_node_info()?;
+    // libvirt encodes versions as major * 1,000,000 + minor * 1,000 + release.
+    let version = conn.get_hyp_version()?;
+
+    Ok(HostInfo {
+        uri: conn.get_uri()?,
+        hostname: conn.get_hostname()?,
+        hypervisor: conn.get_type()?,
+        version: format!(
+            "{}.{}.{}",
+            version / 1_000_000,
+            version / 1_000 % 1_000,
+            version % 1_000
+        ),
+        cpu_model: node.model,
+        cpus: node.cpus,
+        sockets: node.sockets * node.nodes,
+        cores: node.cores,
+        threads: node.threads,
+        memory: node.memory,
+    })
+}
+
+/// Time all host CPUs together have spent busy and in total since boot, in
+/// nanoseconds. Usage is derived from the difference between two of these.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HostCpuTimes {
+    pub busy: u64,
+    pub total: u64,
+}
+
+impl HostCpuTimes {
+    /// Share of the time between `previous` and these times the host CPUs
+    /// were busy, as a percentage.
+    pub fn usage_since(&self, previous: &Self) -> Option<f64> {
+        let total = self.total.checked_sub(previous.total)?;
+        let busy = self.busy.checked_sub(previous.busy)?;
+        (total > 0).then(|| busy as f64 / total as f64 * 100.0)
+    }
+}
+
+/// Reads the CPU times of the host, over all of its CPUs. Fails on drivers
+/// that don't report them.
+pub fn host_cpu_times(conn: &Connect) -> Result<HostCpuTimes, Error> {
+    let all = VIR_NODE_CPU_STATS_ALL_CPUS;
+    let mut nparams: c_int = 0;
+    if unsafe { virNodeGetCPUStats(conn.as_ptr(), all, ptr::null_mut(), &mut nparams, 0) } < 0 {
+        return Err(Error::last_error());
+    }
+    let mut params = vec![
+        virNodeCPUStats {
+            field: [0; 80],
+            value: 0,
+        };
+        nparams as usize
+    ];
+    if unsafe { virNodeGetCPUStats(conn.as_ptr(), all, params.as_mut_ptr(), &mut nparams, 0) } < 0 {
+        return Err(Error::last_error());
+    }
+
+    let mut times = HostCpuTimes::default();
+    for param in params.iter().take(nparams as usize) {
+        let field = unsafe { CStr::from_ptr(param.field.as_ptr()) };
+        match field.to_bytes() {
+            b"kernel" | b"user" => {
+                times.busy += param.value;
+                times.total += param.value;
+            }
+            b"idle" | b"iowait" => times.total += param.value,
+            _ => {}
+        }
+    }
+    Ok(times)
+}
+
+/// Memory of the host in KiB, with page cache and buffers counted as free
+/// since the kernel hands them out on demand.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HostMemory {
+    pub total: u64,
+    pub free: u64,
+}
+
+impl HostMemory {
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.free)
     }
 }
 
-pub fn get_vm_data(conn: &Connect) -> Vec<VmMetrics> {
-    let domains = get_domain_stats(&conn).unwrap();
+/// Reads the memory usage of the host, over all of its NUMA cells. Fails on
+/// drivers that don't report it.
+pub fn host_memory(conn: &Connect) -> Result<HostMemory, Error> {
+    let all = VIR_NODE_MEMORY_STATS_ALL_CELLS;
+    let mut nparams: c_int = 0;
+    if unsafe { virNodeGetMemoryStats(conn.as_ptr(), all, ptr::null_mut(), &mut nparams, 0) } < 0 {
+        return Err(Error::last_error());
+    }
+    let mut params = vec![
+        virNodeMemoryStats {
+            field: [0; 80],
+            value: 0,
+        };
+        nparams as usize
+    ];
+    if unsafe { virNodeGetMemoryStats(conn.as_ptr(), all, params.as_mut_ptr(), &mut nparams, 0) }
+        < 0
+    {
+        return Err(Error::last_error());
+    }
+
+    let mut memory = HostMemory::default();
+    for param in params.iter().take(nparams as usize) {
+        let field = unsafe { CStr::from_ptr(param.field.as_ptr()) };
+        match field.to_bytes() {
+            b"total" => memory.total = param.value,
+            b"free" | b"buffers" | b"cached" => memory.free += param.value,
+            _ => {}
+        }
+    }
+    Ok(memory)
+}
+
+/// Reads the metrics of the running domains, and of the shut off ones too if
+/// `inactive`.
+pub fn get_vm_data(conn: &Connect, inactive: bool) -> Result<Vec<VmMetrics>, Error> {
+    let domains = get_domain_stats(conn, inactive)?;
     let mut vm_data = vec![];
 
     for domain in domains {
-        let mut vm_metrics = VmMetrics::default();
-        vm_metrics.timestamp = Instant::now();
+        let mut vm_metrics = VmMetrics {
+            timestamp: Instant::now(),
+            ..Default::default()
+        };
 
         let record_ptr: *const virDomainStatsRecord = domain.ptr;
         let domain_ptr = unsafe { (*record_ptr).dom };
@@ -92,50 +492,172 @@ pub fn get_vm_data(conn: &Connect) -> Vec<VmMetrics> {
 
         let id = unsafe { virDomainGetID(domain_ptr) };
 
+        let mut uuid = [0; VIR_UUID_STRING_BUFLEN as usize];
+        let uuid = if unsafe { virDomainGetUUIDString(domain_ptr, uuid.as_mut_ptr()) } == 0 {
+            unsafe { CStr::from_ptr(uuid.as_ptr()).to_string_lossy().into_owned() }
+        } else {
+            String::new()
+        };
+
+        let mut autostart: c_int = 0;
+        let autostart_ret = unsafe { virDomainGetAutostart(domain_ptr, &mut autostart) };
+
         vm_metrics.name = domain_name;
         vm_metrics.id = id;
+        vm_metrics.uuid = uuid;
+        vm_metrics.persistent = unsafe { virDomainIsPersistent(domain_ptr) } == 1;
+        vm_metrics.autostart = autostart_ret == 0 && autostart != 0;
+        vm_metrics.managed_save = unsafe { virDomainHasManagedSaveImage(domain_ptr, 0) } == 1;
 
         let params = unsafe { slice::from_raw_parts(params_ptr, nparams as usize) };
-        for param in params {
-            let field = unsafe { CStr::from_ptr(param.field.as_ptr()) }.to_string_lossy();
-
-            match field.as_ref() {
-                "state.state" => {
-                    vm_metrics.status = if unsafe { param.value.ul } == 1 {
-                        true
-                    } else {
-                        false
+        parse_stats_params(&mut vm_metrics, params);
+        if vm_metrics.status.is_active() {
+            vm_metrics.running_since = Some(vm_metrics.timestamp);
+        }
+
+        vm_data.push(vm_metrics);
+    }
+    Ok(vm_data)
+}
+
+/// Fills in the usage rates of `metrics` from the `previous` sample, and
+/// carries over when each domain was first seen running.
+pub fn derive_rates(metrics: &mut [VmMetrics], previous: &[VmMetrics]) {
+    // Domains can appear, disappear or reorder between samples, so match
+    // each one against its previous sample by UUID rather than position.
+    // Names can be reused by a different domain, or change on a rename.
+    let previous: HashMap<&str, &VmMetrics> =
+        previous.iter().map(|m| (m.uuid.as_str(), m)).collect();
+
+    for domain in metrics.iter_mut() {
+        let Some(prev) = previous.get(domain.uuid.as_str()) else {
+            continue;
+        };
+        // Keep counting uptime from when the domain was first seen
+        // running, until it is seen shut off.
+        if domain.running_since.is_some() {
+            domain.running_since = prev.running_since.or(domain.running_since);
+        }
+        let elapsed = domain
+            .timestamp
+            .duration_since(prev.timestamp)
+            .as_secs_f64();
+        if elapsed > 0.0 {
+            let time_diff = domain.cpu_time.saturating_sub(prev.cpu_time) as f64 / 1_000_000_000.0;
+            domain.cpu_usage = (time_diff / elapsed) * 100.0;
+            for (vcpu, prev_vcpu) in domain.vcpu_stats.iter_mut().zip(&prev.vcpu_stats) {
+                let time_diff = vcpu.time.saturating_sub(prev_vcpu.time) as f64 / 1_000_000_000.0;
+                vcpu.usage = (time_diff / elapsed) * 100.0;
+            }
+            domain.net_rx_rate = per_second(domain.net_rx, prev.net_rx, elapsed);
+            domain.net_tx_rate = per_second(domain.net_tx, prev.net_tx, elapsed);
+            domain.disk_rx_rate = per_second(domain.disk_rx, prev.disk_rx, elapsed);
+            domain.disk_wx_rate = per_second(domain.disk_wx, prev.disk_wx, elapsed);
+        }
+    }
+}
+
+/// Rate of change of a byte counter. A counter that went backwards (e.g. the
+/// device was hot-unplugged and re-added) counts as no traffic rather than a spike.
+fn per_second(current: u64, previous: u64, elapsed: f64) -> f64 {
+    current.saturating_sub(previous) as f64 / elapsed
+}
+
+/// Fills `vm_metrics` from the typed parameters of a domain stats record.
+fn parse_stats_params(vm_metrics: &mut VmMetrics, params: &[virTypedParameter]) {
+    for param in params {
+        let field = unsafe { CStr::from_ptr(param.field.as_ptr()) }.to_string_lossy();
+
+        match field.as_ref() {
+            "state.state" => vm_metrics.status = DomainState::from_raw(param_u64(param) as u32),
+            "cpu.time" => vm_metrics.cpu_time = param_u64(param),
+            "vcpu.current" => vm_metrics.vcpus = param_u64(param),
+            "vcpu.maximum" => vm_metrics.vcpus_max = param_u64(param),
+            "balloon.current" => vm_metrics.mem_current = param_u64(param),
+            "balloon.maximum" => vm_metrics.mem_max = param_u64(param),
+            "balloon.rss" => vm_metrics.mem_rss = param_u64(param),
+            "balloon.disk_caches" => vm_metrics.mem_cache = param_u64(param),
+            "net.count" => vm_metrics
+                .interfaces
+                .resize_with(param_u64(param) as usize, NetInterface::default),
+            "block.count" => vm_metrics
+                .disks
+                .resize_with(param_u64(param) as usize, BlockDevice::default),
+
+            field => {
+                if let Some((i, key)) = indexed_field(field, "net.") {
+                    let iface = nth_mut(&mut vm_metrics.interfaces, i);
+                    match key {
+                        "name" => iface.name = param_string(param),
+                        "rx.bytes" => iface.rx_bytes = param_u64(param),
+                        "tx.bytes" => iface.tx_bytes = param_u64(param),
+                        _ => {}
+                    }
+                } else if let Some((i, "time")) = indexed_field(field, "vcpu.") {
+                    nth_mut(&mut vm_metrics.vcpu_stats, i).time = param_u64(param);
+                } else if let Some((i, key)) = indexed_field(field, "block.") {
+                    let disk = nth_mut(&mut vm_metrics.disks, i);
+                    match key {
+                        "name" => disk.name = param_string(param),
+                        "path" => disk.path = param_string(param),
+                        "rd.bytes" => disk.rd_bytes = param_u64(param),
+                        "wr.bytes" => disk.wr_bytes = param_u64(param),
+                        "capacity" => disk.capacity = param_u64(param),
+                        "allocation" => disk.allocation = param_u64(param),
+                        "physical" => disk.physical = param_u64(param),
+                        _ => {}
                     }
                 }
-                "cpu.time" => vm_metrics.cpu_time = unsafe { param.value.ul },
-                "balloon.rss" => vm_metrics.mem_rss = unsafe { param.value.ul },
-                "balloon.disk_caches" => vm_metrics.mem_cache = unsafe { param.value.ul },
-                "net.0.name" => {
-                    vm_metrics.net_name =
-                        unsafe { CStr::from_ptr(param.value.s).to_string_lossy().to_string() }
-                }
-                "net.0.rx.bytes" => vm_metrics.net_rx = unsafe { param.value.ul },
-                "net.0.tx.bytes" => vm_metrics.net_tx = unsafe { param.value.ul },
-                "block.0.name" => {
-                    vm_metrics.disk_name =
-                        unsafe { CStr::from_ptr(param.value.s).to_string_lossy().to_string() }
-                }
-                "block.0.path" => {
-                    vm_metrics.disk_path =
-                        unsafe { CStr::from_ptr(param.value.s).to_string_lossy().to_string() }
-                }
-                "block.0.rd.bytes" => vm_metrics.disk_rx = unsafe { param.value.ul },
-                "block.0.wd.bytes" => vm_metrics.disk_wx = unsafe { param.value.ul },
-
-                _ => {}
             }
         }
-        vm_data.push(vm_metrics);
     }
-    return vm_data;
+
+    vm_metrics.net_rx = vm_metrics.interfaces.iter().map(|i| i.rx_bytes).sum();
+    vm_metrics.net_tx = vm_metrics.interfaces.iter().map(|i| i.tx_bytes).sum();
+    vm_metrics.disk_rx = vm_metrics.disks.iter().map(|d| d.rd_bytes).sum();
+    vm_metrics.disk_wx = vm_metrics.disks.iter().map(|d| d.wr_bytes).sum();
+}
+
+/// Splits an indexed stats field such as `net.2.rx.bytes` into `(2, "rx.bytes")`.
+fn indexed_field<'a>(field: &'a str, prefix: &str) -> Option<(usize, &'a str)> {
+    let (index, key) = field.strip_prefix(prefix)?.split_once('.')?;
+    Some((index.parse().ok()?, key))
+}
+
+/// Returns the `i`th element, growing `items` if the `*.count` param was missing.
+fn nth_mut<T: Default>(items: &mut Vec<T>, i: usize) -> &mut T {
+    if items.len() <= i {
+        items.resize_with(i + 1, T::default);
+    }
+    &mut items[i]
 }
 
-fn get_domain_stats(conn: &Connect) -> Result<Vec<DomainStatsRecord>, Error> {
+/// Reads a numeric typed parameter through the union member matching its
+/// declared type. `state.state` is an int while the counters are unsigned
+/// long longs, so reading every field as `ul` picks up garbage in the upper bits.
+fn param_u64(param: &virTypedParameter) -> u64 {
+    unsafe {
+        match param.type_ as u32 {
+            VIR_TYPED_PARAM_INT => param.value.i as u64,
+            VIR_TYPED_PARAM_UINT => param.value.ui as u64,
+            VIR_TYPED_PARAM_LLONG => param.value.l as u64,
+            VIR_TYPED_PARAM_ULLONG => param.value.ul,
+            VIR_TYPED_PARAM_BOOLEAN => param.value.b as u64,
+            _ => 0,
+        }
+    }
+}
+
+fn param_string(param: &virTypedParameter) -> String {
+    if param.type_ as u32 != VIR_TYPED_PARAM_STRING || unsafe { param.value.s.is_null() } {
+        return String::from("unknown");
+    }
+    unsafe { CStr::from_ptr(param.value.s).to_string_lossy().into_owned() }
+}
+
+/// Reads the stats of the running domains, and of the inactive ones too if
+/// `inactive`.
+fn get_domain_stats(conn: &Connect, inactive: bool) -> Result<Vec<DomainStatsRecord>, Error> {
     let stats_flags = VIR_DOMAIN_STATS_STATE
         | VIR_DOMAIN_STATS_CPU_TOTAL
         | VIR_DOMAIN_STATS_BALLOON
@@ -143,39 +665,1040 @@ fn get_domain_stats(conn: &Connect) -> Result<Vec<DomainStatsRecord>, Error> {
         | VIR_DOMAIN_STATS_INTERFACE
         | VIR_DOMAIN_STATS_BLOCK;
 
-    conn.get_all_domain_stats(
-        stats_flags,
-        VIR_CONNECT_GET_ALL_DOMAINS_STATS_ACTIVE | VIR_CONNECT_GET_ALL_DOMAINS_STATS_INACTIVE,
-    )
+    let mut flags = VIR_CONNECT_GET_ALL_DOMAINS_STATS_ACTIVE;
+    if inactive {
+        flags |= VIR_CONNECT_GET_ALL_DOMAINS_STATS_INACTIVE;
+    }
+    conn.get_all_domain_stats(stats_flags, flags)
+}
+
+/// Snapshots a domain. A disk-only snapshot leaves the guest running, while a
+/// full snapshot of a running domain also saves its memory, pausing it briefly.
+///
+/// `snapshot_name` and `description` are inserted into the snapshot XML as-is
+/// and must not contain markup; a timestamped name is used when none is given.
+pub fn snapshot(
+    conn: &Connect,
+    name: &str,
+    snapshot_name: Option<&str>,
+    description: Option<&str>,
+    disk_only: bool,
+) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let snapshot_name = match snapshot_name {
+        Some(s) => s.to_string(),
+        None => format!("{}-{}", name, Utc::now()),
+    };
+    let xml = format!(
+        r#"
+            <domainsnapshot>
+                <name>{}</name>
+                <description>{}</description>
+            </domainsnapshot>
+        "#,
+        snapshot_name,
+        description.unwrap_or("vmgr snapshot")
+    );
+
+    let flags = if disk_only {
+        VIR_DOMAIN_SNAPSHOT_CREATE_DISK_ONLY
+    } else {
+        0
+    };
+    let mut snapshot = DomainSnapshot::create_xml(&dom, &xml, flags)?;
+    snapshot.free()?;
+    Ok(())
+}
+
+pub fn start(conn: &Connect, name: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.create()?;
+    Ok(())
+}
+
+pub fn stop(conn: &Connect, name: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.shutdown()?;
+    Ok(())
+}
+
+pub fn force_stop(conn: &Connect, name: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.destroy()?;
+    Ok(())
+}
+
+/// Saves the memory of a running domain to disk and stops it. Starting it
+/// again restores from the saved image.
+pub fn managed_save(conn: &Connect, name: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.managed_save(0)?;
+    Ok(())
+}
+
+/// Discards the managed save image of a domain, so it cold boots next time.
+pub fn managed_save_remove(conn: &Connect, name: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.managed_save_remove(0)?;
+    Ok(())
+}
+
+/// Live migrates a running domain to the hypervisor at `dest_uri`, moving its
+/// definition along. Storage has to be shared between the hosts. Blocks until
+/// the migration finished.
+pub fn migrate(conn: &Connect, name: &str, dest_uri: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let mut dconn = Connect::open(dest_uri)?;
+    let flags = VIR_MIGRATE_LIVE | VIR_MIGRATE_PERSIST_DEST | VIR_MIGRATE_UNDEFINE_SOURCE;
+
+    // `Domain::migrate` passes an empty rather than a null name and URI,
+    // which libvirt takes as renaming the domain to "".
+    let migrated = unsafe {
+        virDomainMigrate(
+            dom.as_ptr(),
+            dconn.as_ptr(),
+            flags as c_ulong,
+            ptr::null(),
+            ptr::null(),
+            0,
+        )
+    };
+    let result = if migrated.is_null() {
+        Err(Error::last_error())
+    } else {
+        drop(Domain::new(migrated));
+        Ok(())
+    };
+
+    let _ = dconn.close();
+    result
+}
+
+pub fn reboot(conn: &Connect, name: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.reboot(0)
+}
+
+pub fn pause(conn: &Connect, name: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.suspend()?;
+    Ok(())
+}
+
+pub fn resume(conn: &Connect, name: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.resume()?;
+    Ok(())
 }
 
-pub fn snapshot(conn: &Connect, name: &str) {
-    if let Ok(dom) = Domain::lookup_by_name(&conn, &name) {
-        let xml = format!(
+/// Defines a new persistent qemu/kvm domain with `vcpus` vCPUs, `memory` MiB
+/// of memory, a virtio disk backed by `disk` if given, and a NIC on libvirt's
+/// `default` network. The values are inserted into the domain XML as-is.
+pub fn define_vm(
+    conn: &Connect,
+    name: &str,
+    vcpus: u32,
+    memory: u64,
+    disk: Option<&str>,
+) -> Result<(), Error> {
+    let disk = match disk {
+        Some(path) => format!(
             r#"
-                <domainsnapshot>
-                    <name>{}-{}</name>
-                    <description>vmgr snapshot</description>
-                </domainsnapshot>
-            "#,
-            name,
-            Utc::now().to_string()
-        );
+            <disk type='file' device='disk'>
+                <driver name='qemu' type='{}'/>
+                <source file='{}'/>
+                <target dev='vda' bus='virtio'/>
+            </disk>"#,
+            if path.ends_with(".qcow2") {
+                "qcow2"
+            } else {
+                "raw"
+            },
+            path
+        ),
+        None => String::new(),
+    };
+    let xml = format!(
+        r#"
+        <domain type='kvm'>
+            <name>{}</name>
+            <memory unit='MiB'>{}</memory>
+            <vcpu>{}</vcpu>
+            <os>
+                <type>hvm</type>
+                <boot dev='hd'/>
+            </os>
+            <features>
+                <acpi/>
+                <apic/>
+            </features>
+            <cpu mode='host-passthrough'/>
+            <devices>{}
+                <interface type='network'>
+                    <source network='default'/>
+                    <model type='virtio'/>
+                </interface>
+                <serial type='pty'/>
+                <console type='pty'/>
+                <graphics type='vnc' autoport='yes'/>
+            </devices>
+        </domain>
+        "#,
+        name, memory, vcpus, disk
+    );
+
+    Domain::define_xml(conn, &xml)?;
+    Ok(())
+}
+
+/// Removes a shut off domain along with its snapshot, checkpoint and managed
+/// save metadata and its NVRAM. With `remove_storage`, the disk images (not
+/// cdroms) are deleted as well; those that can't be, e.g. because they aren't
+/// in a storage pool, are returned.
+pub fn undefine(conn: &Connect, name: &str, remove_storage: bool) -> Result<Vec<String>, Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let images = disk_images(&dom.get_xml_desc(0)?);
+
+    dom.undefine_flags(
+        VIR_DOMAIN_UNDEFINE_MANAGED_SAVE
+            | VIR_DOMAIN_UNDEFINE_SNAPSHOTS_METADATA
+            | VIR_DOMAIN_UNDEFINE_CHECKPOINTS_METADATA
+            | VIR_DOMAIN_UNDEFINE_NVRAM,
+    )?;
+
+    if !remove_storage {
+        return Ok(vec![]);
+    }
+    Ok(images
+        .into_iter()
+        .filter(|path| {
+            StorageVol::lookup_by_path(conn, path)
+                .and_then(|vol| vol.delete(0))
+                .is_err()
+        })
+        .collect())
+}
+
+/// Defines `new_name` as a copy of the shut off domain `name`, with copies of
+/// its disk images. The UUID, MAC addresses and NVRAM are left out so libvirt
+/// generates fresh ones. Disk images have to be volumes in a storage pool and
+/// are copied into the same pool, which blocks until the copy is done.
+pub fn clone_vm(conn: &Connect, name: &str, new_name: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let mut xml = dom.get_xml_desc(VIR_DOMAIN_XML_INACTIVE | VIR_DOMAIN_XML_SECURE)?;
+
+    xml = xml.replacen(
+        &format!("<name>{}</name>", name),
+        &format!("<name>{}</name>", new_name),
+        1,
+    );
+    let mut generated = xml_blocks(&xml, "mac")
+        .into_iter()
+        .chain(xml_blocks(&xml, "nvram"))
+        .map(String::from)
+        .collect::<Vec<_>>();
+    for tag in ["uuid", "nvram"] {
+        if let Some(value) = xml_element(&xml, tag) {
+            generated.push(format!("<{0}>{1}</{0}>", tag, value));
+        }
+    }
+    for element in generated {
+        xml = xml.replace(&element, "");
+    }
+
+    let mut copies: Vec<StorageVol> = vec![];
+    for path in disk_images(&xml) {
+        match clone_volume(conn, &path, name, new_name) {
+            Ok(copy) => {
+                xml = xml.replace(&format!("'{}'", path), &format!("'{}'", copy.get_path()?));
+                copies.push(copy);
+            }
+            Err(e) => {
+                delete_volumes(&copies);
+                return Err(e);
+            }
+        }
+    }
+
+    if let Err(e) = Domain::define_xml(conn, &xml) {
+        delete_volumes(&copies);
+        return Err(e);
+    }
+    Ok(())
+}
 
-        let mut snapshot =
-            DomainSnapshot::create_xml(&dom, &xml, VIR_DOMAIN_SNAPSHOT_CREATE_DISK_ONLY).unwrap();
-        snapshot.free().unwrap();
+/// Copies the volume at `path` into its pool, naming the copy after
+/// `new_name` in place of `name`.
+fn clone_volume(
+    conn: &Connect,
+    path: &str,
+    name: &str,
+    new_name: &str,
+) -> Result<StorageVol, Error> {
+    let vol = StorageVol::lookup_by_path(conn, path)?;
+    let pool = StoragePool::lookup_by_volume(&vol)?;
+
+    let vol_name = vol.get_name()?;
+    let copy_name = if vol_name.contains(name) {
+        vol_name.replace(name, new_name)
+    } else {
+        format!("{}-{}", new_name, vol_name)
+    };
+    let format = xml_blocks(&vol.get_xml_desc(0)?, "format")
+        .into_iter()
+        .find_map(|f| xml_attribute(f, "type").map(String::from))
+        .unwrap_or_else(|| String::from("raw"));
+    let xml = format!(
+        r#"
+        <volume>
+            <name>{}</name>
+            <target>
+                <format type='{}'/>
+            </target>
+        </volume>
+        "#,
+        copy_name, format
+    );
+
+    StorageVol::create_xml_from(&pool, &xml, &vol, 0)
+}
+
+/// Deletes volumes created by a failed operation, best effort.
+fn delete_volumes(volumes: &[StorageVol]) {
+    for vol in volumes {
+        let _ = vol.delete(0);
+    }
+}
+
+/// Sets whether a domain starts along with the host. Fails for transient
+/// domains, which have no configuration to persist the flag in.
+pub fn set_autostart(conn: &Connect, name: &str, autostart: bool) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.set_autostart(autostart)?;
+    Ok(())
+}
+
+/// Sets the balloon target of a running domain to `memory` KiB. Fails when
+/// the guest has no balloon driver or `memory` exceeds its maximum.
+pub fn set_memory(conn: &Connect, name: &str, memory: u64) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.set_memory_flags(memory, VIR_DOMAIN_MEM_LIVE)?;
+    Ok(())
+}
+
+/// Changes the number of active vCPUs of a running domain. Fails above its
+/// maximum, or when the guest refuses to unplug vCPUs.
+pub fn set_vcpus(conn: &Connect, name: &str, count: u32) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.set_vcpus_flags(count, VIR_DOMAIN_VCPU_LIVE)?;
+    Ok(())
+}
+
+/// Returns the host CPUs each vCPU of a running domain may run on, by vCPU.
+/// Unpinned vCPUs list every host CPU.
+pub fn vcpu_pins(conn: &Connect, name: &str) -> Result<Vec<Vec<u32>>, Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let cpus = conn.get_node_info()?.cpus as usize;
+    let vcpus = dom.get_vcpus_flags(VIR_DOMAIN_VCPU_LIVE)? as usize;
+    let maplen = cpus.div_ceil(8);
+
+    let mut maps = vec![0u8; vcpus * maplen];
+    let n = unsafe {
+        virDomainGetVcpuPinInfo(
+            dom.as_ptr(),
+            vcpus as c_int,
+            maps.as_mut_ptr(),
+            maplen as c_int,
+            VIR_DOMAIN_AFFECT_LIVE,
+        )
+    };
+    if n < 0 {
+        return Err(Error::last_error());
+    }
+    Ok(maps
+        .chunks(maplen)
+        .take(n as usize)
+        .map(|map| {
+            (0..cpus as u32)
+                .filter(|&cpu| map[cpu as usize / 8] & (1 << (cpu % 8)) != 0)
+                .collect()
+        })
+        .collect())
+}
+
+/// Pins vCPU `vcpu` of a running domain to the host CPUs `cpus`, out of the
+/// `host_cpus` of the host. Fails for vCPUs that aren't active, or drivers
+/// without pinning support.
+pub fn pin_vcpu(
+    conn: &Connect,
+    name: &str,
+    vcpu: u32,
+    cpus: &[u32],
+    host_cpus: u32,
+) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let mut map = vec![0u8; (host_cpus as usize).div_ceil(8)];
+    for &cpu in cpus {
+        map[cpu as usize / 8] |= 1 << (cpu % 8);
+    }
+    dom.pin_vcpu_flags(vcpu, &map, VIR_DOMAIN_AFFECT_LIVE)?;
+    Ok(())
+}
+
+/// Parses a list of CPUs in libvirt's cpuset syntax, e.g. `0-3,6`, all below
+/// `host_cpus`. The list comes back sorted, without duplicates.
+pub fn parse_cpuset(s: &str, host_cpus: u32) -> Result<Vec<u32>, String> {
+    let mut cpus = vec![];
+    for part in s.split(',').map(str::trim) {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let (Ok(first), Ok(last)) = (first.trim().parse::<u32>(), last.trim().parse::<u32>())
+        else {
+            return Err(format!("{} is not a CPU or a range of CPUs", part));
+        };
+        if first > last {
+            return Err(format!("{} is an empty range", part));
+        }
+        if last >= host_cpus {
+            return Err(format!(
+                "the host has CPUs 0-{} only",
+                host_cpus.saturating_sub(1)
+            ));
+        }
+        cpus.extend(first..=last);
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+/// Formats sorted CPUs in libvirt's cpuset syntax, e.g. `0-3,6`.
+pub fn format_cpuset(cpus: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = vec![];
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == cpu => *last = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Presses Ctrl+Alt+Del on the keyboard of a running domain. Fails when its
+/// input device can't take key events.
+pub fn send_ctrl_alt_del(conn: &Connect, name: &str) -> Result<(), Error> {
+    // KEY_LEFTCTRL, KEY_LEFTALT and KEY_DELETE from linux/input-event-codes.h
+    let mut keycodes = [29, 56, 111];
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.send_key(
+        VIR_KEYCODE_SET_LINUX,
+        0,
+        keycodes.as_mut_ptr(),
+        keycodes.len() as i32,
+        0,
+    )
+}
+
+/// A SPICE or VNC display of a running domain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Graphics {
+    /// `spice` or `vnc`, which is also the URI scheme viewers take.
+    pub kind: String,
+    /// Address the display listens on, if bound to a particular one.
+    pub listen: Option<String>,
+    pub port: u16,
+}
+
+/// Returns the first SPICE or VNC display of the running domain `name`, or
+/// `None` when it has none, or only ones without a port (e.g. a socket).
+pub fn graphics(conn: &Connect, name: &str) -> Result<Option<Graphics>, Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let xml = dom.get_xml_desc(0)?;
+
+    for block in xml_blocks(&xml, "graphics") {
+        let Some(kind) = xml_attribute(block, "type").filter(|t| *t == "spice" || *t == "vnc")
+        else {
+            continue;
+        };
+        // The port is -1 until qemu started listening.
+        let Some(port) = xml_attribute(block, "port").and_then(|p| p.parse().ok()) else {
+            continue;
+        };
+        let listen = xml_attribute(block, "listen")
+            .filter(|l| !matches!(*l, "" | "0.0.0.0" | "::"))
+            .map(String::from);
+        return Ok(Some(Graphics {
+            kind: kind.to_string(),
+            listen,
+            port,
+        }));
+    }
+    Ok(None)
+}
+
+/// Grabs the framebuffer of the first screen of a running domain, returning
+/// the image and its MIME type (PPM for qemu). Fails for headless domains.
+pub fn screenshot(conn: &Connect, name: &str) -> Result<(Vec<u8>, String), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let stream = Stream::new(conn, 0)?;
+    let mime = dom.screenshot(&stream, 0, 0)?;
+
+    let mut image = vec![];
+    let mut buf = [0; 64 * 1024];
+    loop {
+        match stream.recv(&mut buf)? {
+            0 => break,
+            n => image.extend_from_slice(&buf[..n]),
+        }
+    }
+    stream.finish()?;
+    Ok((image, mime))
+}
+
+/// Attaches to the first console of a running domain, returning a
+/// non-blocking stream to read its output from and write input to. Fails
+/// when the domain has no serial device, or another client holds the console.
+pub fn open_console(conn: &Connect, name: &str) -> Result<Stream, ConsoleError> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let xml = dom.get_xml_desc(0)?;
+    if xml_blocks(&xml, "serial").is_empty() && xml_blocks(&xml, "console").is_empty() {
+        return Err(ConsoleError::NoSerial);
+    }
+
+    let stream = Stream::new(conn, VIR_STREAM_NONBLOCK)?;
+    // A null device name picks the first console.
+    let ret = unsafe {
+        virDomainOpenConsole(
+            dom.as_ptr(),
+            ptr::null(),
+            stream.as_ptr(),
+            VIR_DOMAIN_CONSOLE_SAFE,
+        )
+    };
+    if ret == -1 {
+        return Err(Error::last_error().into());
+    }
+    Ok(stream)
+}
+
+/// Why a console couldn't be opened.
+#[derive(Debug)]
+pub enum ConsoleError {
+    NoSerial,
+    Libvirt(Error),
+}
+
+impl From<Error> for ConsoleError {
+    fn from(e: Error) -> Self {
+        Self::Libvirt(e)
+    }
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoSerial => write!(f, "no serial device configured"),
+            Self::Libvirt(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Reads whatever a non-blocking stream has available into `buf`. Returns
+/// `None` when nothing is available yet, and `Some(0)` once the stream ended.
+pub fn stream_recv(stream: &Stream, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+    let ret = unsafe { virStreamRecv(stream.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) };
+    match ret {
+        -2 => Ok(None),
+        n if n < 0 => Err(Error::last_error()),
+        n => Ok(Some(n as usize)),
+    }
+}
+
+/// Writes as much of `data` to a non-blocking stream as it takes right now.
+/// Returns `None` when it can't take anything yet.
+pub fn stream_send(stream: &Stream, data: &[u8]) -> Result<Option<usize>, Error> {
+    let ret = unsafe { virStreamSend(stream.as_ptr(), data.as_ptr().cast(), data.len()) };
+    match ret {
+        -2 => Ok(None),
+        n if n < 0 => Err(Error::last_error()),
+        n => Ok(Some(n as usize)),
+    }
+}
+
+/// Asks the guest agent of a running domain for its IP addresses, skipping
+/// loopback. Fails when the agent isn't installed or running in the guest.
+pub fn guest_addresses(conn: &Connect, name: &str) -> Result<Vec<GuestAddress>, Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let interfaces = dom.interface_addresses(VIR_DOMAIN_INTERFACE_ADDRESSES_SRC_AGENT, 0)?;
+
+    Ok(interfaces
+        .into_iter()
+        .filter(|iface| iface.name != "lo")
+        .flat_map(|iface| {
+            iface.addrs.into_iter().map(move |addr| GuestAddress {
+                interface: iface.name.clone(),
+                address: addr.addr,
+                prefix: addr.prefix,
+            })
+        })
+        .collect())
+}
+
+/// Bandwidth caps of a running domain. `None` is no cap.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IoLimits {
+    /// Total throughput cap of each disk, in bytes per second.
+    pub disk: Option<u64>,
+    /// Average inbound and outbound cap of each network interface, in KiB/s.
+    pub net: Option<u64>,
+}
+
+/// Reads the bandwidth caps of the running domain `name` from its live XML:
+/// those of the first disk and the first interface that have one.
+pub fn io_limits(conn: &Connect, name: &str) -> Result<IoLimits, Error> {
+    let xml = Domain::lookup_by_name(conn, name)?.get_xml_desc(0)?;
+    // e.g. <disk ...><iotune><total_bytes_sec>10485760</total_bytes_sec></iotune></disk>
+    let disk = xml_blocks(&xml, "disk")
+        .into_iter()
+        .filter_map(|disk| xml_element(disk, "total_bytes_sec"))
+        .find_map(|bytes| bytes.parse().ok())
+        .filter(|&bytes| bytes > 0);
+    // e.g. <interface ...><bandwidth><inbound average='1024'/></bandwidth></interface>
+    let net = xml_blocks(&xml, "interface")
+        .into_iter()
+        .flat_map(|iface| xml_blocks(iface, "inbound"))
+        .find_map(|inbound| xml_attribute(inbound, "average")?.parse().ok())
+        .filter(|&kib| kib > 0);
+    Ok(IoLimits { disk, net })
+}
+
+/// Caps the throughput of every disk of the running domain `name` at `disk`
+/// bytes per second, and the inbound and outbound average of every interface
+/// at `net` KiB/s. Zero lifts a cap. Fails when the driver or a device
+/// doesn't support throttling.
+pub fn set_io_limits(conn: &Connect, name: &str, disk: u64, net: u64) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let xml = dom.get_xml_desc(0)?;
+
+    for block in xml_blocks(&xml, "disk") {
+        // CD-ROM drives can't be throttled while empty.
+        if xml_attribute(block, "device").is_some_and(|device| device != "disk") {
+            continue;
+        }
+        let Some(target) = target_dev(block) else {
+            continue;
+        };
+        with_params(
+            &[(VIR_DOMAIN_BLOCK_IOTUNE_TOTAL_BYTES_SEC, disk)],
+            false,
+            |params, nparams| unsafe {
+                virDomainSetBlockIoTune(
+                    dom.as_ptr(),
+                    target.as_ptr(),
+                    params,
+                    nparams,
+                    VIR_DOMAIN_AFFECT_LIVE,
+                )
+            },
+        )?;
+    }
+
+    for block in xml_blocks(&xml, "interface") {
+        let Some(target) = target_dev(block) else {
+            continue;
+        };
+        with_params(
+            &[
+                (VIR_DOMAIN_BANDWIDTH_IN_AVERAGE, net),
+                (VIR_DOMAIN_BANDWIDTH_OUT_AVERAGE, net),
+            ],
+            true,
+            |params, nparams| unsafe {
+                virDomainSetInterfaceParameters(
+                    dom.as_ptr(),
+                    target.as_ptr(),
+                    params,
+                    nparams,
+                    VIR_DOMAIN_AFFECT_LIVE,
+                )
+            },
+        )?;
     }
+    Ok(())
 }
 
-pub fn start(conn: &Connect, name: &str) {
-    if let Ok(dom) = Domain::lookup_by_name(&conn, &name) {
-        dom.create().unwrap();
+/// Device name a `<disk>` or `<interface>` element is known by on the host,
+/// e.g. `vda` or `vnet0`.
+fn target_dev(device: &str) -> Option<CString> {
+    let target = xml_blocks(device, "target").into_iter().next()?;
+    CString::new(xml_attribute(target, "dev")?).ok()
+}
+
+/// Passes typed parameters holding `values`, by their NUL terminated names
+/// from `virt::sys`, to `set`. The values are unsigned ints if `uint` and
+/// unsigned long longs otherwise.
+fn with_params(
+    values: &[(&[u8], u64)],
+    uint: bool,
+    set: impl FnOnce(virTypedParameterPtr, c_int) -> c_int,
+) -> Result<(), Error> {
+    let mut params: virTypedParameterPtr = ptr::null_mut();
+    let mut nparams = 0;
+    let mut maxparams = 0;
+    for &(name, value) in values {
+        let name = name.as_ptr().cast();
+        let ret = unsafe {
+            if uint {
+                virTypedParamsAddUInt(
+                    &mut params,
+                    &mut nparams,
+                    &mut maxparams,
+                    name,
+                    value.min(c_uint::MAX.into()) as c_uint,
+                )
+            } else {
+                virTypedParamsAddULLong(&mut params, &mut nparams, &mut maxparams, name, value)
+            }
+        };
+        if ret < 0 {
+            unsafe { virTypedParamsFree(params, nparams) };
+            return Err(Error::last_error());
+        }
+    }
+
+    let ret = set(params, nparams);
+    // Taken before freeing, which may reset the last error.
+    let result = if ret < 0 {
+        Err(Error::last_error())
+    } else {
+        Ok(())
+    };
+    unsafe { virTypedParamsFree(params, nparams) };
+    result
+}
+
+/// Reads what seldom changes about a domain from its XML: its OS type,
+/// architecture and machine type, and its title and description.
+pub fn xml_info(conn: &Connect, uuid: &str) -> Result<(OsInfo, Metadata), Error> {
+    let xml = Domain::lookup_by_uuid_string(conn, uuid)?.get_xml_desc(0)?;
+    let metadata = Metadata {
+        title: xml_element(&xml, "title")
+            .map(xml_unescape)
+            .unwrap_or_default(),
+        description: xml_element(&xml, "description")
+            .map(xml_unescape)
+            .unwrap_or_default(),
+    };
+    Ok((os_info(&xml), metadata))
+}
+
+/// Reads the OS type, architecture and machine type of a domain from its
+/// XML.
+fn os_info(xml: &str) -> OsInfo {
+    // e.g. <os><type arch='x86_64' machine='pc-q35-8.2'>hvm</type>...</os>
+    let os_type = xml_blocks(xml, "os")
+        .into_iter()
+        .next()
+        .and_then(|os| xml_blocks(os, "type").into_iter().next());
+    let Some(os_type) = os_type else {
+        return OsInfo::default();
+    };
+
+    let text = os_type
+        .find('>')
+        .zip(os_type.rfind("</"))
+        .and_then(|(start, end)| os_type.get(start + 1..end))
+        .unwrap_or_default();
+    OsInfo {
+        os_type: text.trim().to_string(),
+        arch: xml_attribute(os_type, "arch")
+            .unwrap_or_default()
+            .to_string(),
+        machine: xml_attribute(os_type, "machine")
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+pub fn get_xml(conn: &Connect, name: &str) -> Result<String, Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.get_xml_desc(0)
+}
+
+/// Returns the persistent definition of a domain for editing, secrets
+/// included so they survive being defined again.
+pub fn get_editable_xml(conn: &Connect, name: &str) -> Result<String, Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    dom.get_xml_desc(VIR_DOMAIN_XML_INACTIVE | VIR_DOMAIN_XML_SECURE)
+}
+
+/// Replaces the definition of the domain with the same UUID as `xml`.
+pub fn redefine(conn: &Connect, xml: &str) -> Result<(), Error> {
+    Domain::define_xml(conn, xml)?;
+    Ok(())
+}
+
+/// Checks that `xml` is a well-formed `<domain>` document, with every
+/// element closed in order, before it is handed to libvirt, which validates
+/// the rest.
+pub fn check_xml(xml: &str) -> Result<(), String> {
+    let mut open: Vec<&str> = vec![];
+    let mut root = None;
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment.find("-->").ok_or("unterminated comment")?;
+            rest = &comment[end + 3..];
+            continue;
+        }
+        let end = rest.find('>').ok_or("unterminated tag")?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if tag.starts_with(['?', '!']) {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim();
+            match open.pop() {
+                Some(opened) if opened == name => {}
+                Some(opened) => return Err(format!("</{}> closes <{}>", name, opened)),
+                None => return Err(format!("</{}> closes nothing", name)),
+            }
+            continue;
+        }
+
+        let name = tag
+            .trim_end_matches('/')
+            .split_whitespace()
+            .next()
+            .ok_or("empty tag")?;
+        if open.is_empty() && root.replace(name).is_some() {
+            return Err(format!("<{}> is outside the root element", name));
+        }
+        if !tag.ends_with('/') {
+            open.push(name);
+        }
+    }
+
+    if let Some(opened) = open.pop() {
+        return Err(format!("<{}> is never closed", opened));
+    }
+    match root {
+        Some("domain") => Ok(()),
+        Some(name) => Err(format!("the root element is <{}>, not <domain>", name)),
+        None => Err(String::from("no <domain> element")),
+    }
+}
+
+/// Lists the snapshots of a domain, newest first.
+pub fn list_snapshots(conn: &Connect, name: &str) -> Result<Vec<SnapshotInfo>, Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    let mut snapshots = vec![];
+
+    for snap in dom.list_all_snapshots(0)? {
+        let xml = snap.get_xml_desc(0)?;
+        snapshots.push(SnapshotInfo {
+            name: snap.get_name()?,
+            description: xml_element(&xml, "description")
+                .unwrap_or_default()
+                .to_string(),
+            state: xml_element(&xml, "state").unwrap_or("unknown").to_string(),
+            created: xml_element(&xml, "creationTime")
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(0),
+        });
+    }
+
+    snapshots.sort_by_key(|s| Reverse(s.created));
+    Ok(snapshots)
+}
+
+pub fn revert_snapshot(conn: &Connect, name: &str, snapshot: &str) -> Result<(), Error> {
+    let dom = Domain::lookup_by_name(conn, name)?;
+    DomainSnapshot::lookup_by_name(&dom, snapshot, 0)?.revert(0)
+}
+
+/// Returns the file or block device backing each `<disk device='disk'>` in a
+/// domain XML.
+fn disk_images(xml: &str) -> Vec<String> {
+    xml_blocks(xml, "disk")
+        .into_iter()
+        .filter(|disk| xml_attribute(disk, "device") == Some("disk"))
+        .filter_map(|disk| {
+            let source = xml_blocks(disk, "source").into_iter().next()?;
+            xml_attribute(source, "file").or_else(|| xml_attribute(source, "dev"))
+        })
+        .map(String::from)
+        .collect()
+}
+
+/// Returns every `<tag ...>` element in `xml`, from its opening `<` up to its
+/// closing tag, or up to the end of its opening tag when self-closing.
+pub(crate) fn xml_blocks<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{} ", tag);
+    let close = format!("</{}>", tag);
+    let mut blocks = vec![];
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start..];
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        let end = if rest[..tag_end].ends_with('/') {
+            tag_end + 1
+        } else {
+            rest.find(&close).map_or(rest.len(), |i| i + close.len())
+        };
+        blocks.push(&rest[..end]);
+        rest = &rest[end..];
     }
+    blocks
 }
 
-pub fn stop(conn: &Connect, name: &str) {
-    if let Ok(dom) = Domain::lookup_by_name(&conn, &name) {
-        dom.destroy().unwrap();
+/// Returns the value of attribute `name` on the opening tag of `element`.
+pub(crate) fn xml_attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let tag = &element[..element.find('>')?];
+    for quote in ['\'', '"'] {
+        let prefix = format!(" {}={}", name, quote);
+        if let Some(start) = tag.find(&prefix) {
+            let value = &tag[start + prefix.len()..];
+            return Some(&value[..value.find(quote)?]);
+        }
+    }
+    None
+}
+
+/// Replaces the entities libvirt escapes text with by the characters they
+/// stand for.
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Returns the text of the first `<tag>` element in `xml`.
+///
+/// This is only meant for the flat, well-known elements libvirt emits, not as
+/// a general XML parser.
+fn xml_element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(xml[start..end].trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(field: &str, type_: u32) -> virTypedParameter {
+        let mut param: virTypedParameter = unsafe { std::mem::zeroed() };
+        for (dst, src) in param.field.iter_mut().zip(field.bytes()) {
+            *dst = src as _;
+        }
+        param.type_ = type_ as c_int;
+        param
+    }
+
+    fn int(field: &str, value: c_int) -> virTypedParameter {
+        let mut param = param(field, VIR_TYPED_PARAM_INT);
+        param.value.i = value;
+        param
+    }
+
+    fn uint(field: &str, value: c_uint) -> virTypedParameter {
+        let mut param = param(field, VIR_TYPED_PARAM_UINT);
+        param.value.ui = value;
+        param
+    }
+
+    fn ullong(field: &str, value: u64) -> virTypedParameter {
+        let mut param = param(field, VIR_TYPED_PARAM_ULLONG);
+        param.value.ul = value;
+        param
+    }
+
+    #[test]
+    fn parses_state_and_counters() {
+        let cpu_time = u64::from(u32::MAX) + 42;
+        let params = [
+            int("state.state", VIR_DOMAIN_RUNNING as c_int),
+            ullong("cpu.time", cpu_time),
+            uint("vcpu.current", 2),
+            ullong("balloon.current", 1 << 21),
+        ];
+
+        let mut metrics = VmMetrics::default();
+        parse_stats_params(&mut metrics, &params);
+
+        assert_eq!(metrics.status, DomainState::Running);
+        assert_eq!(metrics.cpu_time, cpu_time);
+        assert_eq!(metrics.vcpus, 2);
+        assert_eq!(metrics.mem_current, 1 << 21);
+    }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn int_state_ignores_upper_union_bits() {
+        let mut state = param("state.state", VIR_TYPED_PARAM_INT);
+        state.value.ul = 0xdead_beef_0000_0000 | u64::from(VIR_DOMAIN_PAUSED);
+
+        let mut metrics = VmMetrics::default();
+        parse_stats_params(&mut metrics, &[state]);
+
+        assert_eq!(metrics.status, DomainState::Paused);
+    }
+
+    #[test]
+    fn parses_indexed_devices_and_totals() {
+        let params = [
+            uint("net.count", 2),
+            ullong("net.0.rx.bytes", 100),
+            ullong("net.1.rx.bytes", u64::from(u32::MAX) + 1),
+            ullong("net.1.tx.bytes", 7),
+            uint("block.count", 1),
+            ullong("block.0.wr.bytes", 4096),
+            ullong("block.0.rd.bytes", 512),
+        ];
+
+        let mut metrics = VmMetrics::default();
+        parse_stats_params(&mut metrics, &params);
+
+        assert_eq!(metrics.interfaces.len(), 2);
+        assert_eq!(metrics.interfaces[0].rx_bytes, 100);
+        assert_eq!(metrics.interfaces[1].rx_bytes, u64::from(u32::MAX) + 1);
+        assert_eq!(metrics.net_rx, u64::from(u32::MAX) + 101);
+        assert_eq!(metrics.net_tx, 7);
+        assert_eq!(metrics.disks.len(), 1);
+        assert_eq!(metrics.disks[0].wr_bytes, 4096);
+        assert_eq!(metrics.disk_wx, 4096);
+        assert_eq!(metrics.disk_rx, 512);
+    }
+
+    #[test]
+    fn grows_devices_without_count() {
+        let params = [ullong("block.1.wr.bytes", 10)];
+
+        let mut metrics = VmMetrics::default();
+        parse_stats_params(&mut metrics, &params);
+
+        assert_eq!(metrics.disks.len(), 2);
+        assert_eq!(metrics.disks[1].wr_bytes, 10);
     }
 }